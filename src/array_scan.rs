@@ -0,0 +1,416 @@
+use crate::json_output::{EventWriter, LinesWriter, OutputFormat, PrettyJsonWriter};
+use crate::key_path::{KeyPath, KeySegment};
+use eyre::{eyre, Report, Result};
+use json_event_parser::{JsonEvent, JsonReader, JsonWriter};
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// How `--scan` lists the arrays that it finds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ScanFormat {
+    /// One line per array, in aligned columns.
+    #[default]
+    Table,
+    /// A JSON array with an object for each array.
+    Json,
+}
+
+impl FromStr for ScanFormat {
+    type Err = Report;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("table") {
+            Ok(Self::Table)
+        } else if s.eq_ignore_ascii_case("json") {
+            Ok(Self::Json)
+        } else {
+            Err(eyre!("Expecting \"table\" or \"json\", found \"{s}\""))
+        }
+    }
+}
+
+/// An array found while scanning a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrayInfo {
+    pub key_path: KeyPath,
+    /// The number of arrays & objects that the array is nested inside.
+    pub depth: usize,
+    /// The number of elements in the array.
+    pub len: usize,
+    /// The byte offset of the array's `[`.
+    pub start: u64,
+    /// The byte offset just past the array's `]`.
+    pub end: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Offsets {
+    /// How many bytes the parser has consumed.
+    position: u64,
+    /// The offset just past the last byte consumed that was part of a value, rather than whitespace
+    /// or a separator.
+    value_end: u64,
+}
+
+/// A reader that keeps track of how far into the input the JSON parser has got.
+struct CountingReader<R: BufRead> {
+    inner: R,
+    offsets: Rc<Cell<Offsets>>,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let mut offsets = self.offsets.get();
+        if amt > 0 {
+            // The bytes being consumed are still in the buffer, so this doesn't read anything.
+            if let Ok(buf) = self.inner.fill_buf() {
+                let last = buf[..amt.min(buf.len())]
+                    .iter()
+                    .rposition(|b| !matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b',' | b':'));
+                if let Some(last) = last {
+                    offsets.value_end = offsets.position + last as u64 + 1;
+                }
+            }
+        }
+        offsets.position += amt as u64;
+        self.offsets.set(offsets);
+        self.inner.consume(amt);
+    }
+}
+
+/// A container that is open at the current point of the scan.
+enum Open {
+    /// An object, along with the key of the member currently being read.
+    Object(String),
+    Array {
+        start: u64,
+        len: usize,
+    },
+}
+
+/// Read a whole JSON document, calling `on_array` for each array in it. Arrays are passed on as
+/// they end, so nested arrays come before the arrays that contain them. The scan stops at the first
+/// error that `on_array` returns.
+pub fn scan_arrays<R: BufRead>(
+    in_json: R,
+    mut on_array: impl FnMut(ArrayInfo) -> Result<()>,
+) -> Result<()> {
+    let offsets = Rc::new(Cell::new(Offsets::default()));
+    let mut reader = JsonReader::from_reader(CountingReader {
+        inner: in_json,
+        offsets: offsets.clone(),
+    });
+    let mut buffer = Vec::new();
+    let mut stack: Vec<Open> = Vec::new();
+    loop {
+        match reader.read_event(&mut buffer)? {
+            JsonEvent::Eof => return Ok(()),
+            JsonEvent::ObjectKey(key) => {
+                if let Some(Open::Object(current)) = stack.last_mut() {
+                    *current = key.to_string();
+                }
+            }
+            JsonEvent::EndArray => {
+                if let Some(Open::Array { start, len }) = stack.pop() {
+                    let key_path = stack
+                        .iter()
+                        .map(|open| match open {
+                            Open::Object(key) => KeySegment::ObjectKey(key.clone()),
+                            Open::Array { len, .. } => KeySegment::ArrayIndex(len - 1),
+                        })
+                        .collect::<Vec<_>>()
+                        .into();
+                    on_array(ArrayInfo {
+                        key_path,
+                        depth: stack.len(),
+                        len,
+                        start,
+                        end: offsets.get().value_end,
+                    })?;
+                }
+            }
+            JsonEvent::EndObject => {
+                let _ = stack.pop();
+            }
+            event => {
+                if let Some(Open::Array { len, .. }) = stack.last_mut() {
+                    *len += 1;
+                }
+                match event {
+                    JsonEvent::StartArray => stack.push(Open::Array {
+                        start: offsets.get().position - 1,
+                        len: 0,
+                    }),
+                    JsonEvent::StartObject => stack.push(Open::Object(String::new())),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Find the array to copy from when none is named: the root, if it is an array, otherwise the
+/// longest array that is a member of the root, otherwise the first array in the document.
+pub fn find_auto_target<R: BufRead>(in_json: R) -> Result<KeyPath> {
+    let rank = |array: &ArrayInfo| match array.depth {
+        0 | 1 => (array.depth, Reverse(array.len), array.start),
+        _ => (2, Reverse(0), array.start),
+    };
+    let mut best: Option<ArrayInfo> = None;
+    scan_arrays(in_json, |array| {
+        if best.as_ref().is_none_or(|best| rank(&array) < rank(best)) {
+            best = Some(array);
+        }
+        Ok(())
+    })?;
+    best.map(|array| array.key_path)
+        .ok_or_else(|| eyre!("No array found in the input JSON"))
+}
+
+/// Call `on_array` for each array in the document that is nested inside at most `max_depth`
+/// containers, as soon as it ends.
+fn scan_within<R: BufRead>(
+    in_json: R,
+    max_depth: Option<usize>,
+    mut on_array: impl FnMut(&ArrayInfo) -> Result<()>,
+) -> Result<()> {
+    scan_arrays(in_json, |array| {
+        if max_depth.is_none_or(|max_depth| array.depth <= max_depth) {
+            on_array(&array)?;
+        }
+        Ok(())
+    })
+}
+
+/// List every array in the document that is nested inside at most `max_depth` containers, without
+/// copying anything. Each array is listed as soon as it ends, so nested arrays come before the
+/// arrays that contain them, & nothing is held in memory.
+pub fn scan_loop<R: BufRead, W: Write>(
+    in_json: R,
+    out: &mut W,
+    max_depth: Option<usize>,
+    scan_format: ScanFormat,
+    format: OutputFormat,
+) -> Result<()> {
+    match (scan_format, format) {
+        (ScanFormat::Table, _) => {
+            write_table_header(out)?;
+            scan_within(in_json, max_depth, |array| write_table_row(array, out))
+        }
+        (ScanFormat::Json, OutputFormat::Compact) => {
+            write_json(in_json, max_depth, JsonWriter::from_writer(out))
+        }
+        (ScanFormat::Json, OutputFormat::Pretty(indent)) => {
+            write_json(in_json, max_depth, PrettyJsonWriter::new(out, indent))
+        }
+        (ScanFormat::Json, OutputFormat::Lines(separator)) => {
+            write_json(in_json, max_depth, LinesWriter::new(out, separator, 1))
+        }
+        (ScanFormat::Json, OutputFormat::Sequence) => {
+            write_json(in_json, max_depth, LinesWriter::json_seq(out, 1))
+        }
+    }
+}
+
+/// The widths of the number columns of the table. Rows are written as the scan goes, so the widths
+/// are fixed, wide enough for the offsets into a file of many GB.
+const COLUMN_WIDTHS: [usize; 4] = [5, 8, 12, 12];
+
+/// Write the header of the table, with the pointer last so that the root's empty pointer is clear.
+fn write_table_header<W: Write>(out: &mut W) -> Result<()> {
+    let [w0, w1, w2, w3] = COLUMN_WIDTHS;
+    writeln!(
+        out,
+        "{:>w0$}  {:>w1$}  {:>w2$}  {:>w3$}  POINTER",
+        "DEPTH", "LENGTH", "START", "END"
+    )?;
+    Ok(())
+}
+
+fn write_table_row<W: Write>(array: &ArrayInfo, out: &mut W) -> Result<()> {
+    let [w0, w1, w2, w3] = COLUMN_WIDTHS;
+    writeln!(
+        out,
+        "{:>w0$}  {:>w1$}  {:>w2$}  {:>w3$}  {}",
+        array.depth,
+        array.len,
+        array.start,
+        array.end,
+        array.key_path.to_pointer()
+    )?;
+    Ok(())
+}
+
+fn write_json<R: BufRead, E: EventWriter>(
+    in_json: R,
+    max_depth: Option<usize>,
+    mut outj: E,
+) -> Result<()> {
+    outj.write_event(JsonEvent::StartArray)?;
+    scan_within(in_json, max_depth, |array| {
+        outj.write_event(JsonEvent::StartObject)?;
+        outj.write_event(JsonEvent::ObjectKey("pointer"))?;
+        outj.write_event(JsonEvent::String(&array.key_path.to_pointer()))?;
+        let numbers = [
+            ("depth", array.depth as u64),
+            ("length", array.len as u64),
+            ("start", array.start),
+            ("end", array.end),
+        ];
+        for (key, number) in numbers {
+            outj.write_event(JsonEvent::ObjectKey(key))?;
+            outj.write_event(JsonEvent::Number(&number.to_string()))?;
+        }
+        outj.write_event(JsonEvent::EndObject)?;
+        Ok(())
+    })?;
+    outj.write_event(JsonEvent::EndArray)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::array_scan::{find_auto_target, scan_arrays, scan_loop, ArrayInfo, ScanFormat};
+    use crate::json_output::OutputFormat;
+    use crate::key_path::KeyPath;
+    use std::io::BufReader;
+
+    fn scan(json_input_document: &str) -> Vec<ArrayInfo> {
+        let mut arrays = Vec::new();
+        scan_arrays(json_input_document.as_bytes(), |array| {
+            arrays.push(array);
+            Ok(())
+        })
+        .unwrap();
+        arrays
+    }
+
+    #[test]
+    fn test_scan_arrays() {
+        let input = "{\"a\": [1, [2, 3]] , \"b\":{\"c\":[]},\"d\":\"[,]\"}";
+        let arrays = scan(input);
+        assert_eq!(3, arrays.len());
+        assert_eq!(KeyPath::from_kp_str("a[1]").unwrap(), arrays[0].key_path);
+        assert_eq!((2, 2), (arrays[0].depth, arrays[0].len));
+        assert_eq!(
+            "[2, 3]",
+            &input[arrays[0].start as usize..arrays[0].end as usize]
+        );
+        assert_eq!(KeyPath::from_kp_str("a").unwrap(), arrays[1].key_path);
+        assert_eq!((1, 2), (arrays[1].depth, arrays[1].len));
+        assert_eq!(
+            "[1, [2, 3]]",
+            &input[arrays[1].start as usize..arrays[1].end as usize]
+        );
+        assert_eq!(KeyPath::from_kp_str("b.c").unwrap(), arrays[2].key_path);
+        assert_eq!((2, 0), (arrays[2].depth, arrays[2].len));
+        assert_eq!(
+            "[]",
+            &input[arrays[2].start as usize..arrays[2].end as usize]
+        );
+    }
+
+    #[test]
+    fn test_scan_small_buffer() {
+        let input = "{\"a\" : [ 1 , [2,3] ] ,\"b\":[ \"x,y\" ]}";
+        let mut arrays = Vec::new();
+        let reader = BufReader::with_capacity(2, input.as_bytes());
+        scan_arrays(reader, |array| {
+            arrays.push(array);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(scan(input), arrays);
+    }
+
+    #[test]
+    fn test_scan_root_array() {
+        let arrays = scan(" [[], {}] ");
+        assert_eq!(2, arrays.len());
+        assert_eq!(KeyPath::from_kp_str("[0]").unwrap(), arrays[0].key_path);
+        assert_eq!(KeyPath::default(), arrays[1].key_path);
+        assert_eq!(
+            (0, 2, 1, 9),
+            (
+                arrays[1].depth,
+                arrays[1].len,
+                arrays[1].start,
+                arrays[1].end
+            )
+        );
+    }
+
+    fn run_scan(json_input_document: &str, max_depth: Option<usize>, format: ScanFormat) -> String {
+        let mut out = Vec::new();
+        scan_loop(
+            json_input_document.as_bytes(),
+            &mut out,
+            max_depth,
+            format,
+            OutputFormat::Compact,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_scan_table() {
+        let input = "[{\"a~b\":[1,2,3,4,5,6,7,8,9,10]},[[]]]";
+        assert_eq!(
+            "DEPTH    LENGTH         START           END  POINTER\n    \
+                 2        10             8            30  /0/a~0b\n    \
+                 2         0            33            35  /1/0\n    \
+                 1         1            32            36  /1\n    \
+                 0         2             0            37  \n",
+            run_scan(input, None, ScanFormat::Table)
+        );
+        assert_eq!(
+            "DEPTH    LENGTH         START           END  POINTER\n    \
+                 1         1            32            36  /1\n    \
+                 0         2             0            37  \n",
+            run_scan(input, Some(1), ScanFormat::Table)
+        );
+    }
+
+    #[test]
+    fn test_scan_json() {
+        assert_eq!(
+            "[{\"pointer\":\"/0\",\"depth\":1,\"length\":1,\"start\":1,\"end\":4},\
+             {\"pointer\":\"\",\"depth\":0,\"length\":1,\"start\":0,\"end\":5}]",
+            run_scan("[[1]]", None, ScanFormat::Json)
+        );
+        assert_eq!("[]", run_scan("{}", None, ScanFormat::Json));
+    }
+
+    #[test]
+    fn test_find_auto_target() {
+        let find = |input: &str| find_auto_target(input.as_bytes()).unwrap();
+        assert_eq!(KeyPath::default(), find("[1,[2,3,4]]"));
+        assert_eq!(
+            KeyPath::from_kp_str("b").unwrap(),
+            find("{\"a\":[1,2],\"b\":[1,2,3],\"c\":[4,5,6],\"d\":{\"e\":[1,2,3,4]}}")
+        );
+        assert_eq!(
+            KeyPath::from_kp_str("a.b").unwrap(),
+            find("{\"a\":{\"b\":[{},{\"c\":[1]}],\"d\":[1,2]}}")
+        );
+        assert!(find_auto_target("{\"a\":1}".as_bytes()).is_err());
+    }
+}
@@ -1,28 +1,210 @@
-use eyre::{eyre, Result};
+use eyre::Result;
 use json_event_parser::{JsonEvent, JsonReader, JsonWriter};
-use std::io::{BufRead, Write};
+use std::io::{self, BufRead, Write};
 
 use crate::copy_selector::CopySelector;
+use crate::json_input::{
+    next_document, BufferedEvents, DocumentReader, EventReader, Framing, InputFormat, RecordReader,
+};
+use crate::json_output::{EventWriter, LinesWriter, OutputFormat, PrettyJsonWriter};
 
 pub fn copy_loop<R: BufRead, W: Write>(
     in_json: R,
     out_json: &mut W,
     cs: &mut CopySelector,
+    input: InputFormat,
+    format: OutputFormat,
+) -> Result<()> {
+    let documents = matches!(
+        input,
+        InputFormat::Documents(_) | InputFormat::JsonSeqDocuments(_)
+    );
+    for_each_document(in_json, cs, input, |inj, cs| match &format {
+        OutputFormat::Compact => {
+            copy_events(inj, &mut JsonWriter::from_writer(&mut *out_json), cs)?;
+            if documents {
+                out_json.write_all(b"\n")?;
+            }
+            Ok(())
+        }
+        OutputFormat::Pretty(indent) => {
+            copy_events(inj, &mut PrettyJsonWriter::new(&mut *out_json, *indent), cs)
+        }
+        OutputFormat::Lines(separator) => {
+            // Only the elements are written, so the selector should be made with `no_context`
+            let element_depth = if cs.wraps_targets() { 2 } else { 1 };
+            let mut outj = LinesWriter::new(&mut *out_json, separator.clone(), element_depth);
+            copy_events(inj, &mut outj, cs)
+        }
+        OutputFormat::Sequence => {
+            let element_depth = if cs.wraps_targets() { 2 } else { 1 };
+            copy_events(
+                inj,
+                &mut LinesWriter::json_seq(&mut *out_json, element_depth),
+                cs,
+            )
+        }
+    })
+}
+
+/// Call `copy` with the events of each document in `in_json`, letting the selector know when a new
+/// one starts.
+fn for_each_document<R: BufRead>(
+    mut in_json: R,
+    cs: &mut CopySelector,
+    input: InputFormat,
+    mut copy: impl FnMut(&mut dyn EventReader, &mut CopySelector) -> Result<()>,
+) -> Result<()> {
+    match input {
+        InputFormat::Json => copy(&mut JsonReader::from_reader(in_json), cs),
+        InputFormat::Ndjson => copy(&mut RecordReader::new(in_json, Framing::Lines), cs),
+        InputFormat::JsonSeq => copy(&mut RecordReader::new(in_json, Framing::Sequence), cs),
+        InputFormat::Documents(limit) => {
+            let mut documents = 0;
+            while limit.is_none_or(|limit| documents < limit) && next_document(&mut in_json)? {
+                if documents > 0 {
+                    cs.next_document();
+                }
+                copy(&mut DocumentReader::new(&mut in_json), cs)?;
+                documents += 1;
+            }
+            Ok(())
+        }
+        InputFormat::JsonSeqDocuments(limit) => {
+            let mut records = RecordReader::new(in_json, Framing::Sequence);
+            let mut documents = 0;
+            while limit.is_none_or(|limit| documents < limit) {
+                let Some(events) = records.next_record()? else {
+                    break;
+                };
+                if documents > 0 {
+                    cs.next_document();
+                }
+                copy(&mut BufferedEvents::new(events), cs)?;
+                documents += 1;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The number & sizes of the elements selected from the target arrays. Sizes are the lengths of
+/// the elements written as compact JSON.
+#[derive(Debug, Default, PartialEq)]
+pub struct ArrayStats {
+    pub count: usize,
+    pub min_size: u64,
+    pub max_size: u64,
+    pub total_size: u64,
+}
+
+impl ArrayStats {
+    fn add(&mut self, size: u64) {
+        if self.count == 0 || size < self.min_size {
+            self.min_size = size;
+        }
+        self.max_size = self.max_size.max(size);
+        self.total_size += size;
+        self.count += 1;
+    }
+
+    pub fn avg_size(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total_size as f64 / self.count as f64
+        }
+    }
+}
+
+/// A `Write` that only counts the bytes written to it.
+#[derive(Default)]
+struct ByteCount(u64);
+
+impl Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Measures the elements of the target arrays instead of writing them out. Elements are the values
+/// that are `element_depth` containers deep in the copied events.
+struct ElementCounter {
+    element_depth: usize,
+    depth: usize,
+    element: Option<JsonWriter<ByteCount>>,
+    stats: ArrayStats,
+}
+
+impl EventWriter for ElementCounter {
+    fn write_event(&mut self, event: JsonEvent<'_>) -> io::Result<()> {
+        if matches!(event, JsonEvent::EndArray | JsonEvent::EndObject) {
+            self.depth -= 1;
+        }
+        if self.depth == self.element_depth && self.element.is_none() {
+            self.element = Some(JsonWriter::from_writer(ByteCount::default()));
+        }
+        if let Some(element) = &mut self.element {
+            element.write_event(event)?;
+        }
+        match event {
+            JsonEvent::StartArray | JsonEvent::StartObject => self.depth += 1,
+            JsonEvent::ObjectKey(_) => {}
+            _ if self.depth == self.element_depth => {
+                if let Some(element) = self.element.take() {
+                    self.stats.add(element.into_inner().0);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Count the elements selected from the target arrays, without writing anything. The selector
+/// should be made with `no_context`.
+pub fn count_loop<R: BufRead>(
+    in_json: R,
+    cs: &mut CopySelector,
+    input: InputFormat,
+) -> Result<ArrayStats> {
+    let mut counter = ElementCounter {
+        element_depth: if cs.wraps_targets() { 2 } else { 1 },
+        depth: 0,
+        element: None,
+        stats: ArrayStats::default(),
+    };
+    for_each_document(in_json, cs, input, |inj, cs| {
+        copy_events(inj, &mut counter, cs)
+    })?;
+    Ok(counter.stats)
+}
+
+fn copy_events<E: EventWriter>(
+    inj: &mut dyn EventReader,
+    outj: &mut E,
+    cs: &mut CopySelector,
 ) -> Result<()> {
-    let mut inj = JsonReader::from_reader(in_json);
-    let mut outj = JsonWriter::from_writer(out_json);
     let mut buff = Vec::new();
 
     loop {
         let event = inj.read_event(&mut buff)?;
         if event == JsonEvent::Eof {
-            return if cs.target_copied() {
-                Ok(())
-            } else {
-                Err(eyre!("Did not complete JSON copy"))
-            };
+            cs.finish()?;
+            for queued in cs.drain_queued() {
+                outj.write_event(queued.as_json_event())?;
+            }
+            return Ok(());
         }
         let copy_to_out = cs.select(event)?;
+        for queued in cs.drain_queued() {
+            outj.write_event(queued.as_json_event())?;
+        }
         if copy_to_out {
             outj.write_event(event)?;
         }
@@ -31,12 +213,17 @@ pub fn copy_loop<R: BufRead, W: Write>(
 
 #[cfg(test)]
 mod tests {
-    use crate::copy_loop::copy_loop;
-    use crate::copy_selector::CopySelector;
+    use crate::copy_loop::{copy_loop, count_loop, ArrayStats};
+    use crate::copy_selector::{Annotation, ContextPolicy, CopySelector};
+    use crate::json_input::InputFormat;
+    use crate::json_output::{Indent, OutputFormat, Separator};
     use crate::key_path::KeyPath;
+    use crate::predicate::Predicate;
+    use crate::projection::Projection;
     use eyre::Result;
     use std::io::BufReader;
     use std::str;
+    use std::str::FromStr;
 
     fn run_run_headj(
         json_input_document: &str,
@@ -44,12 +231,44 @@ mod tests {
         count: usize,
         skip: usize,
         no_context: bool,
+    ) -> Result<String> {
+        run_headj_formatted(
+            json_input_document,
+            key_path_str,
+            count,
+            skip,
+            no_context,
+            OutputFormat::Compact,
+        )
+    }
+
+    fn run_headj_formatted(
+        json_input_document: &str,
+        key_path_str: &str,
+        count: usize,
+        skip: usize,
+        no_context: bool,
+        format: OutputFormat,
     ) -> Result<String> {
         let key_path = KeyPath::from_kp_str(key_path_str)?;
-        let mut copy_selector = CopySelector::new(key_path, count, skip, no_context);
+        let copy_selector = CopySelector::new(key_path, count, skip, no_context);
+        run_selector(json_input_document, copy_selector, format)
+    }
+
+    fn run_selector(
+        json_input_document: &str,
+        mut copy_selector: CopySelector,
+        format: OutputFormat,
+    ) -> Result<String> {
         let input_reader = BufReader::new(json_input_document.as_bytes());
         let mut output_writer: Vec<u8> = Vec::new();
-        let _ = copy_loop(input_reader, &mut output_writer, &mut copy_selector)?;
+        copy_loop(
+            input_reader,
+            &mut output_writer,
+            &mut copy_selector,
+            InputFormat::Json,
+            format,
+        )?;
         let out_string = str::from_utf8(&output_writer)?;
         Ok(out_string.to_string())
     }
@@ -255,6 +474,87 @@ mod tests {
         assert_eq!("Did not complete JSON copy", e.as_str());
     }
 
+    #[test]
+    /// headj -k 'foo' -n <<- JSON
+    /// {"bar":{"foo":[1,2]},"foo":[3,4,5]}
+    /// JSON
+    /// # Output: [3,4,5]
+    fn test_key_in_sibling_object() {
+        let result = run_run_headj(
+            "{\"bar\":{\"foo\":[1,2]},\"foo\":[3,4,5]}",
+            "foo",
+            100,
+            0,
+            true,
+        )
+        .unwrap();
+        assert_eq!("[3,4,5]", result.as_str());
+    }
+
+    #[test]
+    fn test_key_in_sibling_object_context() {
+        let result = run_run_headj(
+            "{\"bar\":{\"foo\":[1,2,3]},\"foo\":[4,5,6]}",
+            "foo",
+            1,
+            0,
+            false,
+        )
+        .unwrap();
+        assert_eq!("{\"bar\":{\"foo\":[1,2,3]},\"foo\":[4]}", result.as_str());
+    }
+
+    #[test]
+    /// headj -k 'foo' <<- JSON
+    /// {"bar":{"foo":[1,2,3,4,5]}}
+    /// JSON
+    /// # Error: the only "foo" is not at the root
+    fn test_incorrect_key_only_nested() {
+        let e = run_run_headj("{\"bar\":{\"foo\":[1,2,3,4,5]}}", "foo", 2, 2, false)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Did not complete JSON copy", e.as_str());
+    }
+
+    #[test]
+    /// headj -k 'foo.bar' <<- JSON
+    /// {"foo":{"baz":1},"qux":{"bar":[1,2,3]}}
+    /// JSON
+    /// # Error: "bar" is not inside "foo"
+    fn test_incorrect_second_key_in_sibling() {
+        let e = run_run_headj(
+            "{\"foo\":{\"baz\":1},\"qux\":{\"bar\":[1,2,3]}}",
+            "foo.bar",
+            2,
+            0,
+            false,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!("Did not complete JSON copy", e.as_str());
+    }
+
+    #[test]
+    fn test_incorrect_key_in_array_element() {
+        let e = run_run_headj("{\"list\":[{\"foo\":[1,2]}]}", "foo", 2, 0, true)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Did not complete JSON copy", e.as_str());
+    }
+
+    #[test]
+    fn test_second_key_after_nested_decoy() {
+        let result = run_run_headj(
+            "{\"foo\":{\"x\":{\"bar\":[1]},\"bar\":[2,3]}}",
+            "foo.bar",
+            100,
+            0,
+            true,
+        )
+        .unwrap();
+        assert_eq!("[2,3]", result.as_str());
+    }
+
     #[test]
     /// headj -k 'foo' <<- JSON
     /// [1,2,3,4,5]
@@ -290,4 +590,1057 @@ mod tests {
             .to_string();
         assert_eq!("Did not complete JSON copy", e.as_str());
     }
+
+    #[test]
+    /// headj -k 'shards[2].rows' -c 2 -n <<- JSON
+    /// {"shards":[{"rows":[1,2]},{"rows":[3,4]},{"rows":[5,6,7]}]}
+    /// JSON
+    /// # Output: [5,6]
+    fn test_bracket_index_key() {
+        let result = run_run_headj(
+            "{\"shards\":[{\"rows\":[1,2]},{\"rows\":[3,4]},{\"rows\":[5,6,7]}]}",
+            "shards[2].rows",
+            2,
+            0,
+            true,
+        )
+        .unwrap();
+        assert_eq!("[5,6]", result.as_str());
+    }
+
+    #[test]
+    fn test_dotted_index_key_context() {
+        let result = run_run_headj(
+            "{\"shards\":[{\"rows\":[1,2]},{\"rows\":[3,4,5]}],\"n\":2}",
+            "shards.1.rows",
+            1,
+            1,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"shards\":[{\"rows\":[1,2]},{\"rows\":[4]}],\"n\":2}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    /// headj -k '[1][0]' -n <<- JSON
+    /// [[1,2],[[3,4],[5]],[6]]
+    /// JSON
+    /// # Output: [3,4]
+    fn test_nested_array_index() {
+        let result = run_run_headj("[[1,2],[[3,4],[5]],[6]]", "[1][0]", 100, 0, true).unwrap();
+        assert_eq!("[3,4]", result.as_str());
+    }
+
+    #[test]
+    fn test_index_of_non_array() {
+        let e = run_run_headj("[1,[2,3],4]", "[2]", 100, 0, true)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Expecting Json array, found Number(\"4\")", e.as_str());
+    }
+
+    #[test]
+    fn test_index_out_of_range() {
+        let e = run_run_headj("{\"foo\":[[1],[2]]}", "foo[2]", 100, 0, false)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Did not complete JSON copy", e.as_str());
+    }
+
+    #[test]
+    ///     headj -c 2 -s 2 -f <<- JSON
+    ///     [1,2,3,4,5]
+    ///     JSON
+    ///     # Output: [\n  3,\n  4\n]
+    fn test_list_2_count_2_skip_format() {
+        let format = OutputFormat::Pretty(Indent::default());
+        let result = run_headj_formatted("[1,2,3,4,5]", "", 2, 2, true, format).unwrap();
+        assert_eq!("[\n  3,\n  4\n]\n", result.as_str());
+    }
+
+    #[test]
+    fn test_object_1_key_context_format_tab() {
+        let format = OutputFormat::Pretty(Indent::Tab);
+        let result =
+            run_headj_formatted("{\"a\":1,\"foo\":[1,2,3]}", "foo", 1, 0, false, format).unwrap();
+        assert_eq!(
+            "{\n\t\"a\": 1,\n\t\"foo\": [\n\t\t1\n\t]\n}\n",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_empty_window_format() {
+        let format = OutputFormat::Pretty(Indent::default());
+        let result = run_headj_formatted("{\"foo\":[1,2]}", "foo", 1, 5, false, format).unwrap();
+        assert_eq!("{\n  \"foo\": []\n}\n", result.as_str());
+    }
+
+    #[test]
+    /// headj -k 'foo' -t 2 -n <<- JSON
+    /// {"foo":[1,2,3,4,5]}
+    /// JSON
+    /// # Output: [4,5]
+    fn test_tail_no_context() {
+        let key_path = KeyPath::from_kp_str("foo").unwrap();
+        let copy_selector = CopySelector::new(key_path, 100, 0, true)
+            .with_tail(2)
+            .unwrap();
+        let result = run_selector(
+            "{\"foo\":[1,2,3,4,5]}",
+            copy_selector,
+            OutputFormat::Compact,
+        )
+        .unwrap();
+        assert_eq!("[4,5]", result.as_str());
+    }
+
+    #[test]
+    fn test_tail_complex_elements_context() {
+        let key_path = KeyPath::from_kp_str("foo").unwrap();
+        let copy_selector = CopySelector::new(key_path, 100, 0, false)
+            .with_tail(2)
+            .unwrap();
+        let result = run_selector(
+            "{\"foo\":[{\"a\":[1]},{\"b\":[2]},{\"c\":[3]}],\"bar\":true}",
+            copy_selector,
+            OutputFormat::Compact,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"foo\":[{\"b\":[2]},{\"c\":[3]}],\"bar\":true}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_tail_longer_than_array() {
+        let copy_selector = CopySelector::new(KeyPath::default(), 100, 0, true)
+            .with_tail(10)
+            .unwrap();
+        let result = run_selector("[1,[2],3]", copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("[1,[2],3]", result.as_str());
+    }
+
+    #[test]
+    fn test_tail_zero() {
+        let copy_selector = CopySelector::new(KeyPath::default(), 100, 0, true)
+            .with_tail(0)
+            .unwrap();
+        let result = run_selector("[1,2,3]", copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("[]", result.as_str());
+    }
+
+    fn run_sample(json_input_document: &str, count: usize, seed: u64) -> String {
+        let copy_selector = CopySelector::new(KeyPath::default(), 100, 0, true);
+        let copy_selector = copy_selector.with_sample(count, seed).unwrap();
+        run_selector(json_input_document, copy_selector, OutputFormat::Compact).unwrap()
+    }
+
+    #[test]
+    fn test_sample_is_reproducible() {
+        let input = format!(
+            "[{}]",
+            (0..1000)
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        let result = run_sample(&input, 10, 42);
+        assert_eq!(result, run_sample(&input, 10, 42));
+        assert_ne!(result, run_sample(&input, 10, 43));
+        let values: Vec<usize> = result[1..result.len() - 1]
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect();
+        assert_eq!(10, values.len());
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_sample_larger_than_array() {
+        let result = run_sample("[{\"a\":1},[2],3]", 10, 7);
+        assert_eq!("[{\"a\":1},[2],3]", result.as_str());
+    }
+
+    #[test]
+    fn test_sample_with_skip() {
+        let copy_selector = CopySelector::new(KeyPath::default(), 100, 3, true)
+            .with_sample(5, 1)
+            .unwrap();
+        let result = run_selector("[1,2,3,4,5]", copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("[4,5]", result.as_str());
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let input = "{\"foo\":[1,2,3,4,5,6,7,8,9,10],\"bar\":0}";
+        for (rate, expected) in [(0.0, "{\"foo\":[],\"bar\":0}"), (1.0, input)] {
+            let key_path = KeyPath::from_kp_str("foo").unwrap();
+            let copy_selector = CopySelector::new(key_path, 100, 0, false)
+                .with_sample_rate(rate, 5)
+                .unwrap();
+            let result = run_selector(input, copy_selector, OutputFormat::Compact).unwrap();
+            assert_eq!(expected, result.as_str());
+        }
+        let copy_selector = CopySelector::new(KeyPath::default(), 100, 0, true);
+        assert!(copy_selector.with_sample_rate(1.5, 5).is_err());
+    }
+
+    #[test]
+    /// headj --step 3 -s 1 -c 3 <<- JSON
+    /// [0,1,2,3,4,5,6,7,8,9,10,11]
+    /// JSON
+    /// # Output: [1,4,7]
+    fn test_step_skip_count() {
+        let copy_selector = CopySelector::new(KeyPath::default(), 3, 1, true)
+            .with_step(3)
+            .unwrap();
+        let result = run_selector(
+            "[0,1,2,3,4,5,6,7,8,9,10,11]",
+            copy_selector,
+            OutputFormat::Compact,
+        )
+        .unwrap();
+        assert_eq!("[1,4,7]", result.as_str());
+    }
+
+    #[test]
+    fn test_step_complex_elements_context() {
+        let key_path = KeyPath::from_kp_str("foo").unwrap();
+        let copy_selector = CopySelector::new(key_path, 100, 0, false)
+            .with_step(2)
+            .unwrap();
+        let result = run_selector(
+            "{\"foo\":[{\"a\":[1]},{\"b\":[2]},{\"c\":[3]},{\"d\":[4]},{\"e\":[5]}]}",
+            copy_selector,
+            OutputFormat::Compact,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"foo\":[{\"a\":[1]},{\"c\":[3]},{\"e\":[5]}]}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_step_zero() {
+        let copy_selector = CopySelector::new(KeyPath::default(), 100, 0, true);
+        assert!(copy_selector.with_step(0).is_err());
+    }
+
+    #[test]
+    /// headj -k 'data.items[1:3]' -n <<- JSON
+    /// {"data":{"items":[0,1,2,3,4]}}
+    /// JSON
+    /// # Output: [1,2]
+    fn test_key_path_slice() {
+        let result = run_run_headj(
+            "{\"data\":{\"items\":[0,1,2,3,4]}}",
+            "data.items[1:3]",
+            100,
+            0,
+            true,
+        )
+        .unwrap();
+        assert_eq!("[1,2]", result.as_str());
+    }
+
+    #[test]
+    fn test_key_path_slices() {
+        let input = "[0,1,2,3,4,5,6,7,8,9]";
+        for (slice, expected) in [
+            ("[::3]", "[0,3,6,9]"),
+            ("[2:]", "[2,3,4,5,6,7,8,9]"),
+            ("[-3:]", "[7,8,9]"),
+            ("[-50:]", "[0,1,2,3,4,5,6,7,8,9]"),
+            ("[-4:-1]", "[6,7,8]"),
+            ("[-5:7]", "[5,6]"),
+            ("[-6::2]", "[4,6,8]"),
+            ("[2:-5]", "[2,3,4]"),
+            ("[1:-2:3]", "[1,4,7]"),
+            ("[8:-5]", "[]"),
+            ("[-2:-4]", "[]"),
+            ("[3:1]", "[]"),
+        ] {
+            let result = run_run_headj(input, slice, 100, 0, true).unwrap();
+            assert_eq!(expected, result.as_str(), "slice {slice}");
+        }
+    }
+
+    #[test]
+    fn test_key_path_negative_slice_context() {
+        let result = run_run_headj(
+            "{\"foo\":[{\"a\":[1]},{\"b\":[2]},{\"c\":[3]},{\"d\":[4]}],\"bar\":[5]}",
+            "foo[1:-1]",
+            100,
+            0,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"foo\":[{\"b\":[2]},{\"c\":[3]}],\"bar\":[5]}",
+            result.as_str()
+        );
+    }
+
+    fn run_targets(
+        json_input_document: &str,
+        key_path_strs: &[&str],
+        count: usize,
+        no_context: bool,
+    ) -> Result<String> {
+        let mut key_paths = key_path_strs.iter().map(|key| KeyPath::from_kp_str(key));
+        let mut copy_selector = CopySelector::new(key_paths.next().unwrap()?, count, 0, no_context);
+        for key_path in key_paths {
+            copy_selector = copy_selector.with_target(key_path?);
+        }
+        run_selector(json_input_document, copy_selector, OutputFormat::Compact)
+    }
+
+    #[test]
+    /// headj -k users -k orders -c 2 <<- JSON
+    /// {"users":[1,2,3],"meta":{"v":1},"orders":[4,5,6],"events":[7,8,9]}
+    /// JSON
+    /// # Output: {"users":[1,2],"meta":{"v":1},"orders":[4,5],"events":[7,8,9]}
+    fn test_multiple_targets_context() {
+        let result = run_targets(
+            "{\"users\":[1,2,3],\"meta\":{\"v\":1},\"orders\":[4,5,6],\"events\":[7,8,9]}",
+            &["users", "orders"],
+            2,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"users\":[1,2],\"meta\":{\"v\":1},\"orders\":[4,5],\"events\":[7,8,9]}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_multiple_targets_own_slices() {
+        let result = run_targets(
+            "{\"a\":{\"users\":[1,2,3]},\"orders\":[4,5,6],\"events\":[7,8,9]}",
+            &["a.users[1:]", "orders[-1:]", "events"],
+            1,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"a\":{\"users\":[2,3]},\"orders\":[6],\"events\":[7]}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_multiple_targets_no_context() {
+        let result = run_targets(
+            "{\"users\":[1,2,3],\"orders\":[4,5,6]}",
+            &["orders", "users"],
+            2,
+            true,
+        )
+        .unwrap();
+        assert_eq!("[[1,2],[4,5]]", result.as_str());
+    }
+
+    #[test]
+    fn test_nested_targets() {
+        let result =
+            run_targets("{\"a\":[[1,2,3],[4,5,6],[7]]}", &["a", "a[1]"], 2, false).unwrap();
+        assert_eq!("{\"a\":[[1,2,3],[4,5]]}", result.as_str());
+        let result = run_targets(
+            "{\"a\":[[1,2,3],[4,5,6],[7,8]]}",
+            &["a[-2:]", "a[1][:1]"],
+            2,
+            false,
+        )
+        .unwrap();
+        assert_eq!("{\"a\":[[4],[7,8]]}", result.as_str());
+    }
+
+    #[test]
+    fn test_overlapping_targets() {
+        let result = run_targets("{\"a\":{\"b\":[1,2,3]}}", &["..b", "a.b"], 1, false).unwrap();
+        assert_eq!("{\"a\":{\"b\":[1]}}", result.as_str());
+        let result = run_targets("{\"a\":{\"b\":[1,2,3]}}", &["a.b[1:]", "a.*"], 1, false).unwrap();
+        assert_eq!("{\"a\":{\"b\":[2,3]}}", result.as_str());
+    }
+
+    #[test]
+    fn test_slices_conflict_with_tail_and_sample() {
+        let copy_selector = || {
+            CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 100, 0, false)
+                .with_target(KeyPath::from_kp_str("b[1:3]").unwrap())
+        };
+        let e = copy_selector().with_tail(1).err().unwrap();
+        assert_eq!(
+            "A slice in the key path \"/b\" cannot be combined with --tail",
+            e.to_string()
+        );
+        assert!(copy_selector().with_sample(1, 7).is_err());
+        assert!(copy_selector().with_sample_rate(0.5, 7).is_err());
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 100, 0, false);
+        assert!(copy_selector.with_tail(1).is_ok());
+    }
+
+    #[test]
+    fn test_slices_conflict_with_step_skip_and_count() {
+        let sliced = || CopySelector::new(KeyPath::from_kp_str("a[1:]").unwrap(), 100, 0, false);
+        let e = sliced().with_step(3).err().unwrap();
+        assert_eq!(
+            "A slice at the end of the key path takes the place of --step, so they cannot be combined",
+            e.to_string()
+        );
+        assert!(sliced().with_step(1).is_ok());
+        assert!(sliced().check_not_all_sliced("--count").is_err());
+        // A key without a slice still uses them
+        let mixed = sliced().with_target(KeyPath::from_kp_str("b").unwrap());
+        assert!(mixed.check_not_all_sliced("--skip").is_ok());
+        assert!(mixed.with_step(3).is_ok());
+    }
+
+    #[test]
+    fn test_multiple_targets_one_missing() {
+        let e = run_targets("{\"users\":[1,2,3]}", &["users", "orders"], 2, false)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Did not complete JSON copy", e.as_str());
+    }
+
+    #[test]
+    fn test_missing_targets_named() {
+        let e = run_targets(
+            "{\"users\":[1,2,3]}",
+            &["orders", "users", "a/b.2"],
+            2,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "Did not complete JSON copy: No array found at \"/orders\", \"/a~1b/2\"",
+            format!("{e:#}")
+        );
+    }
+
+    #[test]
+    /// headj -k 'regions.*.items' -c 1 <<- JSON
+    /// {"regions":{"us":{"items":[1,2]},"eu":{"items":[3,4]},"ap":{"other":[5,6]}}}
+    /// JSON
+    /// # Output: {"regions":{"us":{"items":[1]},"eu":{"items":[3]},"ap":{"other":[5,6]}}}
+    fn test_wildcard_key() {
+        let result = run_targets(
+            "{\"regions\":{\"us\":{\"items\":[1,2]},\"eu\":{\"items\":[3,4]},\"ap\":{\"other\":[5,6]}}}",
+            &["regions.*.items"],
+            1,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"regions\":{\"us\":{\"items\":[1]},\"eu\":{\"items\":[3]},\"ap\":{\"other\":[5,6]}}}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_wildcard_index() {
+        let input = "{\"shards\":[{\"rows\":[1,2,3]},{\"rows\":\"none\"},{\"rows\":[4,5]}]}";
+        let result = run_targets(input, &["shards[*].rows"], 1, false).unwrap();
+        assert_eq!(
+            "{\"shards\":[{\"rows\":[1]},{\"rows\":\"none\"},{\"rows\":[4]}]}",
+            result.as_str()
+        );
+        let result = run_targets(input, &["shards[*].rows"], 1, true).unwrap();
+        assert_eq!("[[1],[4]]", result.as_str());
+    }
+
+    #[test]
+    fn test_wildcard_does_not_match_other_containers() {
+        let e = run_targets("{\"a\":[[1,2]]}", &["a.*"], 1, false)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Did not complete JSON copy", e);
+        let e = run_targets("{\"a\":{\"b\":[1,2]}}", &["a[*]"], 1, false)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Did not complete JSON copy", e);
+    }
+
+    #[test]
+    /// headj -k '..records' -c 1 <<- JSON
+    /// {"a":{"records":[1,2]},"b":[{"records":[3,4]},{"records":{"n":5}}],"records":[6,7]}
+    /// JSON
+    /// # Output: {"a":{"records":[1]},"b":[{"records":[3]},{"records":{"n":5}}],"records":[6]}
+    fn test_descendants() {
+        let input = "{\"a\":{\"records\":[1,2]},\"b\":[{\"records\":[3,4]},{\"records\":{\"n\":5}}],\"records\":[6,7]}";
+        let result = run_targets(input, &["..records"], 1, false).unwrap();
+        assert_eq!(
+            "{\"a\":{\"records\":[1]},\"b\":[{\"records\":[3]},{\"records\":{\"n\":5}}],\"records\":[6]}",
+            result.as_str()
+        );
+        let result = run_targets(input, &["b..records"], 1, true).unwrap();
+        assert_eq!("[[3]]", result.as_str());
+    }
+
+    #[test]
+    fn test_nested_descendants() {
+        let input = "{\"r\":[{\"r\":[1,2,3]},{\"r\":[4,5,6]},7]}";
+        let result = run_targets(input, &["..r"], 2, false).unwrap();
+        assert_eq!("{\"r\":[{\"r\":[1,2]},{\"r\":[4,5]}]}", result.as_str());
+        let input = "{\"r\":[{\"r\":[[1,2],[3,4]]},[5,6]]}";
+        let result = run_targets(input, &["..r[1]"], 1, false).unwrap();
+        assert_eq!("{\"r\":[{\"r\":[[1,2],[3]]},[5]]}", result.as_str());
+        let e = run_targets(input, &["..q"], 1, false)
+            .unwrap_err()
+            .to_string();
+        assert_eq!("Did not complete JSON copy", e);
+    }
+
+    fn run_all_arrays(
+        json_input_document: &str,
+        count: usize,
+        max_depth: Option<usize>,
+        no_context: bool,
+    ) -> Result<String> {
+        let copy_selector =
+            CopySelector::new(KeyPath::default(), count, 0, no_context).with_all_arrays(max_depth);
+        run_selector(json_input_document, copy_selector, OutputFormat::Compact)
+    }
+
+    #[test]
+    /// headj --all-arrays 2 <<- JSON
+    /// {"a":[1,2,3],"b":{"c":[[1,2,3],[4],[5]]},"d":"x"}
+    /// JSON
+    /// # Output: {"a":[1,2],"b":{"c":[[1,2],[4]]},"d":"x"}
+    fn test_all_arrays() {
+        let result = run_all_arrays(
+            "{\"a\":[1,2,3],\"b\":{\"c\":[[1,2,3],[4],[5]]},\"d\":\"x\"}",
+            2,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"a\":[1,2],\"b\":{\"c\":[[1,2],[4]]},\"d\":\"x\"}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_all_arrays_max_depth() {
+        let input = "[[1,2,3],{\"a\":[1,2,3]},[[1,2,3]]]";
+        let result = run_all_arrays(input, 2, Some(0), false).unwrap();
+        assert_eq!("[[1,2,3],{\"a\":[1,2,3]}]", result.as_str());
+        let result = run_all_arrays(input, 2, Some(1), false).unwrap();
+        assert_eq!("[[1,2],{\"a\":[1,2,3]}]", result.as_str());
+        let result = run_all_arrays(input, 2, Some(2), false).unwrap();
+        assert_eq!("[[1,2],{\"a\":[1,2]}]", result.as_str());
+    }
+
+    #[test]
+    fn test_all_arrays_without_arrays() {
+        let result = run_all_arrays("{\"a\":{\"b\":1}}", 2, None, false).unwrap();
+        assert_eq!("{\"a\":{\"b\":1}}", result.as_str());
+    }
+
+    #[test]
+    fn test_all_arrays_no_context() {
+        let result = run_all_arrays("{\"a\":[1,2,3],\"b\":[[4,5,6]]}", 1, None, true).unwrap();
+        assert_eq!("[[1],[[4]]]", result.as_str());
+    }
+
+    fn run_where(
+        json_input_document: &str,
+        key: &str,
+        predicate: &str,
+        count: usize,
+        no_context: bool,
+    ) -> Result<String> {
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str(key)?, count, 0, no_context)
+            .with_predicate(predicate.parse()?);
+        run_selector(json_input_document, copy_selector, OutputFormat::Compact)
+    }
+
+    #[test]
+    /// headj -k rows --where 'status == "failed"' -c 2 <<- JSON
+    /// {"rows":[{"id":1,"status":"ok"},{"id":2,"status":"failed"},{"id":3,"status":"failed"},{"id":4,"status":"failed"}]}
+    /// JSON
+    /// # Output: {"rows":[{"id":2,"status":"failed"},{"id":3,"status":"failed"}]}
+    fn test_where() {
+        let result = run_where(
+            "{\"rows\":[{\"id\":1,\"status\":\"ok\"},{\"id\":2,\"status\":\"failed\"},{\"id\":3,\"status\":\"failed\"},{\"id\":4,\"status\":\"failed\"}]}",
+            "rows",
+            "status == \"failed\"",
+            2,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"rows\":[{\"id\":2,\"status\":\"failed\"},{\"id\":3,\"status\":\"failed\"}]}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    fn test_where_scalars_and_windows() {
+        let input = "[5,1500,20,3000,7,1001]";
+        let result = run_where(input, "", ". > 1000", 100, true).unwrap();
+        assert_eq!("[1500,3000,1001]", result.as_str());
+        let result = run_where(input, "[1:]", ". > 1000", 100, true).unwrap();
+        assert_eq!("[3000,1001]", result.as_str());
+        let result = run_where(input, "[-2:]", ". < 100", 100, true).unwrap();
+        assert_eq!("[20,7]", result.as_str());
+        let result = run_where(input, "", ". > 5000", 100, false).unwrap();
+        assert_eq!("[]", result.as_str());
+    }
+
+    #[test]
+    fn test_where_nested_targets() {
+        let input = "{\"a\":[{\"ok\":true,\"b\":[1,2]},{\"ok\":false,\"b\":[3]},{\"ok\":true,\"b\":[4,5,6]}]}";
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 1, 0, false)
+            .with_target(KeyPath::from_kp_str("a[*].b").unwrap())
+            .with_predicate(Predicate::from_str("ok || . > 4").unwrap());
+        let result = run_selector(input, copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("{\"a\":[{\"ok\":true,\"b\":[]}]}", result.as_str());
+    }
+
+    #[test]
+    fn test_count_where() {
+        let mut copy_selector = CopySelector::new(KeyPath::default(), usize::MAX, 0, true)
+            .with_predicate(Predicate::from_str("!.x").unwrap());
+        let stats = count_loop(
+            BufReader::new("[{\"x\":1},{},{\"x\":0},{\"x\":null}]".as_bytes()),
+            &mut copy_selector,
+            InputFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(2, stats.count);
+    }
+
+    fn fields(field_strs: &[&str]) -> Vec<KeyPath> {
+        field_strs
+            .iter()
+            .map(|field| KeyPath::from_kp_str(field).unwrap())
+            .collect()
+    }
+
+    #[test]
+    /// headj -k users --fields id,profile.name -c 2 <<- JSON
+    /// {"users":[{"id":1,"profile":{"name":"a","bio":"..."},"tags":["x"]},{"id":2,"profile":{"name":"b"}},{"id":3}]}
+    /// JSON
+    /// # Output: {"users":[{"id":1,"profile":{"name":"a"}},{"id":2,"profile":{"name":"b"}}]}
+    fn test_fields() {
+        let input = "{\"users\":[{\"id\":1,\"profile\":{\"name\":\"a\",\"bio\":\"...\"},\"tags\":[\"x\"]},{\"id\":2,\"profile\":{\"name\":\"b\"}},{\"id\":3}],\"n\":3}";
+        let projection = Projection::include(fields(&["id", "profile.name"])).unwrap();
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("users").unwrap(), 2, 0, false)
+            .with_projection(projection);
+        let result = run_selector(input, copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!(
+            "{\"users\":[{\"id\":1,\"profile\":{\"name\":\"a\"}},{\"id\":2,\"profile\":{\"name\":\"b\"}}],\"n\":3}",
+            result.as_str()
+        );
+
+        // Held back elements are projected before they are held
+        let projection = Projection::exclude(fields(&["profile", "tags"])).unwrap();
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("users").unwrap(), 0, 0, true)
+            .with_tail(2)
+            .unwrap()
+            .with_projection(projection);
+        let result = run_selector(input, copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("[{\"id\":2},{\"id\":3}]", result.as_str());
+    }
+
+    #[test]
+    fn test_fields_where() {
+        // The predicate sees the fields that are projected away
+        let input = "[{\"id\":1,\"status\":\"ok\"},{\"id\":2,\"status\":\"failed\"}]";
+        let copy_selector = CopySelector::new(KeyPath::default(), 10, 0, true)
+            .with_predicate(Predicate::from_str("status == \"failed\"").unwrap())
+            .with_projection(Projection::include(fields(&["id"])).unwrap());
+        let result = run_selector(input, copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("[{\"id\":2}]", result.as_str());
+    }
+
+    #[test]
+    fn test_fields_nested_targets() {
+        // Only the elements of the outermost target are projected
+        let input = "{\"a\":[{\"x\":1,\"b\":[{\"x\":2,\"y\":3}],\"c\":4}]}";
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 10, 0, false)
+            .with_target(KeyPath::from_kp_str("a[*].b").unwrap())
+            .with_projection(Projection::include(fields(&["b[*].y", "c"])).unwrap());
+        let result = run_selector(input, copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("{\"a\":[{\"b\":[{\"y\":3}],\"c\":4}]}", result.as_str());
+    }
+
+    fn run_count(json_input_document: &str, key_path_strs: &[&str]) -> Result<ArrayStats> {
+        let mut key_paths = key_path_strs.iter().map(|key| KeyPath::from_kp_str(key));
+        let mut copy_selector = CopySelector::new(key_paths.next().unwrap()?, usize::MAX, 0, true);
+        for key_path in key_paths {
+            copy_selector = copy_selector.with_target(key_path?);
+        }
+        count_loop(
+            BufReader::new(json_input_document.as_bytes()),
+            &mut copy_selector,
+            InputFormat::Json,
+        )
+    }
+
+    #[test]
+    /// headj --count-only --sizes -k items <<- JSON
+    /// {"items":[1, "two", {"a": [3]}, []], "other": [1, 2]}
+    /// JSON
+    /// # Output:
+    /// # count: 4
+    /// # min_bytes: 1
+    /// # max_bytes: 9
+    /// # avg_bytes: 4.25
+    fn test_count() {
+        let stats = run_count(
+            "{\"items\":[1, \"two\", {\"a\": [3]}, []], \"other\": [1, 2]}",
+            &["items"],
+        )
+        .unwrap();
+        let expected = ArrayStats {
+            count: 4,
+            min_size: 1,
+            max_size: 9,
+            total_size: 17,
+        };
+        assert_eq!(expected, stats);
+        assert_eq!(4.25, stats.avg_size());
+    }
+
+    #[test]
+    fn test_count_empty_and_slice() {
+        let stats = run_count("[]", &[""]).unwrap();
+        assert_eq!(ArrayStats::default(), stats);
+        assert_eq!(0.0, stats.avg_size());
+        let stats = run_count("{\"a\":[[1],[2,3],[4,5,6]]}", &["a[1:]"]).unwrap();
+        assert_eq!((2, 5, 7), (stats.count, stats.min_size, stats.max_size));
+    }
+
+    #[test]
+    fn test_count_several_arrays() {
+        let stats = run_count("{\"a\":[[1],[2]],\"b\":[[3]]}", &["a", "b"]).unwrap();
+        assert_eq!((3, 3, 3), (stats.count, stats.min_size, stats.max_size));
+        let stats = run_count("{\"a\":{\"x\":[1,2],\"y\":[3]}}", &["a.*"]).unwrap();
+        assert_eq!(3, stats.count);
+        assert!(run_count("{\"a\":[1]}", &["b"]).is_err());
+    }
+
+    fn run_context(json_input_document: &str, key: &str, context: ContextPolicy) -> String {
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str(key).unwrap(), 2, 0, false)
+            .with_context(context);
+        run_selector(json_input_document, copy_selector, OutputFormat::Compact).unwrap()
+    }
+
+    #[test]
+    /// headj -k data.items -c 2 --context path-only <<- JSON
+    /// {"meta":{"name":"x","tags":[1,2]},"data":{"total":3,"items":[1,2,3],"links":{}},"other":[4,5]}
+    /// JSON
+    /// # Output: {"data":{"items":[1,2]}}
+    fn test_context_path_only() {
+        let input = "{\"meta\":{\"name\":\"x\",\"tags\":[1,2]},\"data\":{\"total\":3,\"items\":[1,2,3],\"links\":{}},\"other\":[4,5]}";
+        assert_eq!(
+            "{\"data\":{\"items\":[1,2]}}",
+            run_context(input, "data.items", ContextPolicy::PathOnly).as_str()
+        );
+        assert_eq!(
+            "{\"meta\":{\"name\":\"x\",\"tags\":[1,2]},\"data\":{\"total\":3,\"items\":[1,2],\"links\":{}},\"other\":[4,5]}",
+            run_context(input, "data.items", ContextPolicy::Full).as_str()
+        );
+        // Wildcards keep every container that they could lead through
+        let input = "{\"a\":[{\"x\":1,\"b\":[1,2,3]},{\"b\":[4]},5]}";
+        assert_eq!(
+            "{\"a\":[{\"b\":[1,2]},{\"b\":[4]}]}",
+            run_context(input, "a[*].b", ContextPolicy::PathOnly).as_str()
+        );
+    }
+
+    #[test]
+    fn test_context_summarize() {
+        let context = ContextPolicy::Summarize {
+            elements: 1,
+            string_len: 3,
+        };
+        let input = "{\"note\":\"abcdef\",\"big\":[{\"s\":\"abcd\"},2,3],\"a\":[{\"b\":[\"xyzzy\",2,3],\"c\":\"no\"},{\"b\":[4]}]}";
+        assert_eq!(
+            "{\"note\":\"abc\u{2026}\",\"big\":[{\"s\":\"abc\u{2026}\"}],\"a\":[{\"b\":[\"xyzzy\",2],\"c\":\"no\"},{\"b\":[4]}]}",
+            run_context(input, "a[*].b", context).as_str()
+        );
+    }
+
+    fn run_annotated(json_input_document: &str, copy_selector: CopySelector) -> String {
+        let copy_selector = copy_selector.with_annotation(Annotation::Object);
+        run_selector(json_input_document, copy_selector, OutputFormat::Compact).unwrap()
+    }
+
+    #[test]
+    /// headj -k items -s 1 -c 2 --annotate <<- JSON
+    /// {"items":[1,2,3,4,5]}
+    /// JSON
+    /// # Output: {"items":[{"$headj_elided":1},2,3,{"$headj_elided":2}]}
+    fn test_annotate() {
+        let input = "{\"items\":[1,2,3,4,5]}";
+        let items = || KeyPath::from_kp_str("items").unwrap();
+        let result = run_annotated(input, CopySelector::new(items(), 2, 1, false));
+        assert_eq!(
+            "{\"items\":[{\"$headj_elided\":1},2,3,{\"$headj_elided\":2}]}",
+            result.as_str()
+        );
+        let result = run_annotated(
+            input,
+            CopySelector::new(items(), 0, 0, false)
+                .with_tail(2)
+                .unwrap(),
+        );
+        assert_eq!("{\"items\":[{\"$headj_elided\":3},4,5]}", result.as_str());
+        let result = run_annotated(input, CopySelector::new(items(), 9, 0, false));
+        assert_eq!("{\"items\":[1,2,3,4,5]}", result.as_str());
+        // Each gap between the copied elements is marked where it is
+        let copy_selector = CopySelector::new(items(), 9, 0, false)
+            .with_step(2)
+            .unwrap();
+        assert_eq!(
+            "{\"items\":[1,{\"$headj_elided\":1},3,{\"$headj_elided\":1},5]}",
+            run_annotated(input, copy_selector).as_str()
+        );
+        let copy_selector = CopySelector::new(items(), 0, 0, false)
+            .with_sample(2, 0)
+            .unwrap();
+        assert_eq!(
+            "{\"items\":[{\"$headj_elided\":1},2,{\"$headj_elided\":1},4,{\"$headj_elided\":1}]}",
+            run_annotated(input, copy_selector).as_str()
+        );
+        let result = run_annotated(input, CopySelector::new(items(), 0, 0, false));
+        assert_eq!("{\"items\":[{\"$headj_elided\":5}]}", result.as_str());
+        let copy_selector = CopySelector::new(items(), 1, 0, true)
+            .with_predicate(Predicate::from_str(". > 2").unwrap())
+            .with_annotation(Annotation::String("... {} more".to_string()));
+        let result = run_selector(input, copy_selector, OutputFormat::Compact).unwrap();
+        assert_eq!("[\"... 2 more\",3,\"... 2 more\"]", result.as_str());
+    }
+
+    #[test]
+    fn test_annotate_nested_and_context() {
+        let input = "{\"a\":[[1,2,3],[4],[5,6]],\"b\":[7,8,9]}";
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 2, 0, false)
+            .with_target(KeyPath::from_kp_str("a[*]").unwrap())
+            .with_context(ContextPolicy::Summarize {
+                elements: 1,
+                string_len: 10,
+            });
+        assert_eq!(
+            "{\"a\":[[1,2,{\"$headj_elided\":1}],[4],{\"$headj_elided\":1}],\"b\":[7,{\"$headj_elided\":2}]}",
+            run_annotated(input, copy_selector).as_str()
+        );
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 0, 0, false)
+            .with_sample(1, 7)
+            .unwrap();
+        let result = run_annotated(input, copy_selector);
+        assert_eq!(
+            "{\"a\":[{\"$headj_elided\":2},[5,6]],\"b\":[7,8,9]}",
+            result.as_str()
+        );
+    }
+
+    #[test]
+    /// headj --ndjson -s 1 -c 2 <<- JSON
+    /// {"id":1}
+    /// {"id":2}
+    /// not json
+    /// {"id":3}
+    /// {"id":4}
+    /// JSON
+    /// # Output: [{"id":2},{"id":3}]
+    fn test_ndjson() {
+        let input = "{\"id\":1}\n{\"id\":2}\nnot json\n{\"id\":3,\"x\":[1,2]}\n{\"id\":4}\n";
+        let mut copy_selector = CopySelector::new(KeyPath::default(), 2, 1, false).with_projection(
+            Projection::include(vec![KeyPath::from_kp_str("id").unwrap()]).unwrap(),
+        );
+        let mut output_writer: Vec<u8> = Vec::new();
+        copy_loop(
+            BufReader::new(input.as_bytes()),
+            &mut output_writer,
+            &mut copy_selector,
+            InputFormat::Ndjson,
+            OutputFormat::Compact,
+        )
+        .unwrap();
+        assert_eq!(
+            "[{\"id\":2},{\"id\":3}]",
+            str::from_utf8(&output_writer).unwrap()
+        );
+
+        let mut copy_selector =
+            CopySelector::new(KeyPath::from_kp_str("[*].x").unwrap(), 1, 0, true);
+        let stats = count_loop(
+            BufReader::new(input.as_bytes()),
+            &mut copy_selector,
+            InputFormat::Ndjson,
+        )
+        .unwrap();
+        assert_eq!(1, stats.count);
+    }
+
+    #[test]
+    /// headj -k a --output-format ndjson -c 2 <<- JSON
+    /// {"a":[{"id":1},[2],"three"]}
+    /// JSON
+    /// # Output:
+    /// # {"id":1}
+    /// # [2]
+    fn test_ndjson_output() {
+        let input = "{\"a\":[{\"id\":1},[2],\"three\"],\"b\":[4,5]}";
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 2, 0, true);
+        let format = OutputFormat::Lines(Separator::default());
+        let result = run_selector(input, copy_selector, format).unwrap();
+        assert_eq!("{\"id\":1}\n[2]\n", result.as_str());
+
+        let copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 1, 0, true)
+            .with_target(KeyPath::from_kp_str("b").unwrap());
+        let format = OutputFormat::Lines(Separator::from_str("\\0").unwrap());
+        let result = run_selector(input, copy_selector, format).unwrap();
+        assert_eq!("{\"id\":1}\x004\x00", result.as_str());
+    }
+
+    #[test]
+    /// headj --json-seq --output-format json-seq -s 1 <<- JSON
+    /// \x1e{"id":1}
+    /// \x1e{"id":
+    /// \x1e2
+    /// JSON
+    /// # Output: \x1e2\n
+    fn test_json_seq() {
+        let input = "\x1e{\"id\":1}\n\x1e{\"id\":\n\x1e2\n\x1e3";
+        let mut copy_selector = CopySelector::new(KeyPath::default(), 100, 1, true);
+        let mut output_writer: Vec<u8> = Vec::new();
+        copy_loop(
+            BufReader::new(input.as_bytes()),
+            &mut output_writer,
+            &mut copy_selector,
+            InputFormat::JsonSeq,
+            OutputFormat::Sequence,
+        )
+        .unwrap();
+        assert_eq!("\x1e2\n", str::from_utf8(&output_writer).unwrap());
+
+        let input = "\x1e{\"a\":[1,2,3]}\n\x1e{\"a\":[4]}\n\x1e{\"a\":[5,6]}\n";
+        let mut copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 1, 0, false);
+        let mut output_writer: Vec<u8> = Vec::new();
+        copy_loop(
+            BufReader::new(input.as_bytes()),
+            &mut output_writer,
+            &mut copy_selector,
+            InputFormat::JsonSeqDocuments(Some(2)),
+            OutputFormat::Compact,
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"a\":[1]}\n{\"a\":[4]}\n",
+            str::from_utf8(&output_writer).unwrap()
+        );
+
+        // Records are always elements, so the documents run on into one sequence
+        let mut copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 1, 0, true);
+        let mut output_writer: Vec<u8> = Vec::new();
+        copy_loop(
+            BufReader::new(input.as_bytes()),
+            &mut output_writer,
+            &mut copy_selector,
+            InputFormat::JsonSeqDocuments(None),
+            OutputFormat::Sequence,
+        )
+        .unwrap();
+        assert_eq!(
+            "\x1e1\n\x1e4\n\x1e5\n",
+            str::from_utf8(&output_writer).unwrap()
+        );
+    }
+
+    fn run_documents(
+        json_input_document: &str,
+        copy_selector: &mut CopySelector,
+        limit: Option<usize>,
+    ) -> Result<String> {
+        let mut output_writer: Vec<u8> = Vec::new();
+        copy_loop(
+            BufReader::new(json_input_document.as_bytes()),
+            &mut output_writer,
+            copy_selector,
+            InputFormat::Documents(limit),
+            OutputFormat::Compact,
+        )?;
+        Ok(str::from_utf8(&output_writer)?.to_string())
+    }
+
+    #[test]
+    /// headj -k a -c 2 --multi-document <<- JSON
+    /// {"a":[1,2,3]}{"a":[4]}
+    /// {"a":[5,6,7]}
+    /// JSON
+    /// # Output:
+    /// # {"a":[1,2]}
+    /// # {"a":[4]}
+    /// # {"a":[5,6]}
+    fn test_documents() {
+        let input = "{\"a\":[1,2,3]}{\"a\":[4]}\n{\"a\":[5,6,7],\"b\":true}\n";
+        let key = || KeyPath::from_kp_str("a").unwrap();
+        let result =
+            run_documents(input, &mut CopySelector::new(key(), 2, 0, false), None).unwrap();
+        assert_eq!(
+            "{\"a\":[1,2]}\n{\"a\":[4]}\n{\"a\":[5,6],\"b\":true}\n",
+            result.as_str()
+        );
+        let result =
+            run_documents(input, &mut CopySelector::new(key(), 2, 0, true), Some(2)).unwrap();
+        assert_eq!("[1,2]\n[4]\n", result.as_str());
+        assert!(run_documents(
+            "[1] {\"b\":[]}",
+            &mut CopySelector::new(key(), 2, 0, true),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_documents_across() {
+        let input = "{\"a\":[1,2,3]} {\"a\":[4,5]} {\"a\":[6,7,8]}";
+        let mut copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 3, 1, true)
+            .with_annotation(Annotation::Object)
+            .with_windows_across_documents()
+            .unwrap();
+        let result = run_documents(input, &mut copy_selector, None).unwrap();
+        assert_eq!(
+            "[{\"$headj_elided\":1},2,3]\n[4,{\"$headj_elided\":1}]\n[{\"$headj_elided\":3}]\n",
+            result.as_str()
+        );
+
+        let mut copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 2, 1, true)
+            .with_predicate(Predicate::from_str(". > 3").unwrap())
+            .with_windows_across_documents()
+            .unwrap();
+        let result = run_documents(input, &mut copy_selector, None).unwrap();
+        assert_eq!("[]\n[5]\n[6]\n", result.as_str());
+
+        let mut copy_selector = CopySelector::new(KeyPath::from_kp_str("a").unwrap(), 100, 0, true)
+            .with_windows_across_documents()
+            .unwrap();
+        let stats = count_loop(
+            BufReader::new(input.as_bytes()),
+            &mut copy_selector,
+            InputFormat::Documents(None),
+        )
+        .unwrap();
+        assert_eq!(8, stats.count);
+
+        assert!(CopySelector::new(KeyPath::default(), 1, 0, true)
+            .with_tail(1)
+            .unwrap()
+            .with_windows_across_documents()
+            .is_err());
+    }
 }
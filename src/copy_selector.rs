@@ -1,121 +1,1094 @@
-use crate::key_path::{KeyPath, OwnedJsonEvent};
-use eyre::{eyre, Result};
+use crate::key_path::{KeyPath, KeySegment, OwnedJsonEvent, Slice, SliceBound};
+use crate::predicate::Predicate;
+use crate::projection::{Projected, Projection};
+use eyre::{eyre, Report, Result};
 use json_event_parser::JsonEvent;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::VecDeque;
+use std::str::FromStr;
 
+/// A JSON array or object that is open at the current point in the document.
 #[derive(Debug, PartialEq)]
-enum TargetPlacement {
-    Before,
-    Inside,
-    After,
+enum Container {
+    Object,
+    /// An array, along with the index of the next element that will be read from it.
+    Array(usize),
 }
 
-struct JsonFileState {
-    keys: KeyPath,
-    target_placement: TargetPlacement,
-    target_index: usize,
-    keys_index: usize,
-    sub_elements: usize,
+/// How far along the key path of a target the member currently being read has got.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PathState {
+    target: usize,
+    /// The number of segments of the key path that lead to the member.
+    matched: usize,
+}
+
+/// An open container, along with the targets whose key paths the member currently being read from
+/// it lies on. A member is only on a key path if its container's member is too, so matches are
+/// always anchored at the root of the document.
+#[derive(Debug)]
+struct Frame {
+    container: Container,
+    on_path: Vec<PathState>,
 }
 
-impl JsonFileState {
-    fn new(keys: KeyPath) -> Self {
+impl Frame {
+    fn new(container: Container) -> Self {
         Self {
-            keys,
-            target_placement: TargetPlacement::Before,
-            target_index: 0,
-            keys_index: 0,
-            sub_elements: 0,
+            container,
+            on_path: Vec::new(),
         }
     }
+}
+
+/// Which elements of a target array are copied.
+#[derive(Clone, Debug)]
+enum Window {
+    /// Skip `skip` elements, then copy every `step`th element until `count` have been copied, like
+    /// the Python slice `[skip:skip + count * step:step]`.
+    Head {
+        skip: usize,
+        count: usize,
+        step: usize,
+    },
+    /// Copy the elements of a slice with a bound that is relative to the end of the array.
+    Relative(Slice),
+    /// Copy a uniformly random sample of `count` elements after the first `skip`.
+    Reservoir { skip: usize, count: usize },
+    /// Copy each element after the first `skip` with probability `rate`.
+    Bernoulli { skip: usize, rate: f64 },
+}
 
-    fn in_sub_element(&self) -> bool {
-        self.sub_elements != 0
+impl Window {
+    fn from_slice(slice: Slice) -> Self {
+        match (slice.start, slice.stop) {
+            (SliceBound::FromStart(start), None) => Self::Head {
+                skip: start,
+                count: usize::MAX,
+                step: slice.step,
+            },
+            (SliceBound::FromStart(start), Some(SliceBound::FromStart(stop))) => Self::Head {
+                skip: start,
+                count: stop.saturating_sub(start).div_ceil(slice.step),
+                step: slice.step,
+            },
+            _ => Self::Relative(slice),
+        }
     }
 
-    fn next_element(&mut self, event: &JsonEvent) {
-        match event {
-            JsonEvent::StartArray | JsonEvent::StartObject => self.sub_elements += 1,
-            JsonEvent::EndArray | JsonEvent::EndObject => self.sub_elements -= 1,
-            _ => {}
+    fn skip(&self) -> usize {
+        match self {
+            Self::Head { skip, .. }
+            | Self::Reservoir { skip, .. }
+            | Self::Bernoulli { skip, .. } => *skip,
+            Self::Relative(_) => 0,
+        }
+    }
+}
+
+/// The progress of a `Window` through a target array, & what it has decided about the element
+/// currently being read.
+enum Selection {
+    Head {
+        skip: usize,
+        count: usize,
+        step: usize,
+        copy: bool,
+    },
+    /// The last `held` elements are held back, along with their indexes, until it is known whether
+    /// they are part of the slice. Elements that cannot be part of it are held as `None`.
+    Relative {
+        slice: Slice,
+        held: usize,
+        elements: VecDeque<(usize, Option<Vec<OwnedJsonEvent>>)>,
+        current: Option<Vec<OwnedJsonEvent>>,
+    },
+    /// Reservoir sampling. Each kept element is stored along with its index, so the sample can be
+    /// written out in order.
+    Reservoir {
+        skip: usize,
+        count: usize,
+        elements: Vec<(usize, Vec<OwnedJsonEvent>)>,
+        slot: Option<usize>,
+    },
+    Bernoulli {
+        skip: usize,
+        rate: f64,
+        keep: bool,
+    },
+}
+
+impl Selection {
+    fn new(window: &Window) -> Self {
+        match *window {
+            Window::Head { skip, count, step } => Self::Head {
+                skip,
+                count,
+                step,
+                copy: false,
+            },
+            Window::Relative(slice) => {
+                let held = match (slice.start, slice.stop) {
+                    (SliceBound::FromEnd(held), _) | (_, Some(SliceBound::FromEnd(held))) => held,
+                    _ => 0,
+                };
+                Self::Relative {
+                    slice,
+                    held,
+                    elements: VecDeque::new(),
+                    current: None,
+                }
+            }
+            Window::Reservoir { skip, count } => Self::Reservoir {
+                skip,
+                count,
+                elements: Vec::new(),
+                slot: None,
+            },
+            Window::Bernoulli { skip, rate } => Self::Bernoulli {
+                skip,
+                rate,
+                keep: false,
+            },
+        }
+    }
+
+    /// Decide what to do with the element at `index`, which is about to be read.
+    fn start_element(&mut self, index: usize, rng: &mut ChaCha8Rng) {
+        match self {
+            Self::Head {
+                skip,
+                count,
+                step,
+                copy,
+            } => {
+                *copy = index >= *skip
+                    && (index - *skip) % *step == 0
+                    && (index - *skip) / *step < *count;
+            }
+            Self::Relative { slice, current, .. } => {
+                *current = match slice.start {
+                    SliceBound::FromStart(start) => {
+                        index >= start && (index - start) % slice.step == 0
+                    }
+                    SliceBound::FromEnd(_) => true,
+                }
+                .then(Vec::new);
+            }
+            Self::Reservoir {
+                skip,
+                count,
+                elements,
+                slot,
+            } => {
+                *slot = None;
+                if index >= *skip {
+                    // Decide where, if anywhere, this element goes in the reservoir
+                    let seen = index - *skip;
+                    *slot = if seen < *count {
+                        elements.push((index, Vec::new()));
+                        Some(seen)
+                    } else {
+                        let replace = rng.gen_range(0..=seen);
+                        (replace < *count).then(|| {
+                            elements[replace] = (index, Vec::new());
+                            replace
+                        })
+                    };
+                }
+            }
+            Self::Bernoulli { skip, rate, keep } => {
+                *keep = index >= *skip && rng.gen_bool(*rate);
+            }
+        }
+    }
+
+    /// Finish the element at `index`, returning the element, if any, that is now known to be
+    /// selected, along with its index.
+    fn end_element(&mut self, index: usize) -> Option<(usize, Vec<OwnedJsonEvent>)> {
+        if let Self::Relative {
+            slice,
+            held,
+            elements,
+            current,
+        } = self
+        {
+            elements.push_back((index, current.take()));
+            if elements.len() > *held {
+                // An element this far from the end is only part of the slice if the slice starts
+                // from the beginning of the array
+                if let Some((index, Some(events))) = elements.pop_front() {
+                    if let SliceBound::FromStart(_) = slice.start {
+                        return Some((index, events));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Finish an array of `len` elements, returning the elements that were held back, along with
+    /// their indexes.
+    fn end_array(&mut self, len: usize) -> Vec<(usize, Vec<OwnedJsonEvent>)> {
+        match self {
+            Self::Relative {
+                slice, elements, ..
+            } => std::mem::take(elements)
+                .into_iter()
+                .filter(|(index, _)| slice.contains(*index, len))
+                .filter_map(|(index, events)| Some((index, events?)))
+                .collect(),
+            Self::Reservoir { elements, .. } => {
+                let mut elements = std::mem::take(elements);
+                elements.sort_unstable_by_key(|(index, _)| *index);
+                elements
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the element currently being read is left out of the output.
+    fn dropping(&self) -> bool {
+        match self {
+            Self::Head { copy, .. } => !copy,
+            Self::Relative { current, .. } => current.is_none(),
+            Self::Reservoir { slot, .. } => slot.is_none(),
+            Self::Bernoulli { keep, .. } => !keep,
+        }
+    }
+
+    /// Where the events of the element currently being read are held back, if they are.
+    fn capture(&mut self) -> Option<&mut Vec<OwnedJsonEvent>> {
+        match self {
+            Self::Relative { current, .. } => current.as_mut(),
+            Self::Reservoir {
+                elements,
+                slot: Some(slot),
+                ..
+            } => Some(&mut elements[*slot].1),
+            _ => None,
+        }
+    }
+}
+
+/// How much of the document around the target arrays is copied, when it is copied at all.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContextPolicy {
+    /// Copy everything around the target arrays.
+    #[default]
+    Full,
+    /// Copy only the arrays & objects that contain the target arrays, & the keys that lead to them.
+    PathOnly,
+    /// Copy the first `elements` elements of the other arrays, & the first `string_len` characters
+    /// of strings.
+    Summarize { elements: usize, string_len: usize },
+}
+
+impl FromStr for ContextPolicy {
+    type Err = Report;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("full") {
+            Ok(Self::Full)
+        } else if s.eq_ignore_ascii_case("path-only") {
+            Ok(Self::PathOnly)
+        } else if s.eq_ignore_ascii_case("summarize") {
+            Ok(Self::Summarize {
+                elements: 3,
+                string_len: 80,
+            })
+        } else {
+            Err(eyre!(
+                "Expecting \"full\", \"path-only\" or \"summarize\", found \"{s}\""
+            ))
         }
-        if self.sub_elements == 0 {
-            self.target_index += 1;
+    }
+}
+
+/// A key path to a target array, & the window used for it if it ends with a slice.
+struct Target {
+    keys: KeyPath,
+    window: Option<Window>,
+    /// When windows carry on across documents, the number of elements in the target's arrays in
+    /// earlier documents, & the number of those that matched the predicate.
+    seen: usize,
+    matched: usize,
+}
+
+/// A target array that is being read.
+struct ActiveWindow {
+    /// The number of open containers, including the target array itself.
+    depth: usize,
+    selection: Selection,
+    /// Whether elements have to match the predicate to be selected.
+    filtered: bool,
+    /// When filtered, the element currently being read, held back until it can be tested.
+    pending: Option<Vec<OwnedJsonEvent>>,
+    /// The number of elements that have matched the predicate. The selection only sees these, so
+    /// the window counts matching elements.
+    matched: usize,
+    /// When annotating a filtered window, the index in the array of each matching element.
+    positions: Vec<usize>,
+    /// The index in the array just past the last element that was passed on.
+    next: usize,
+    /// The target whose key path led to the array, if any.
+    target: Option<usize>,
+    /// The index that the selection sees for the first element of the array, & for the first
+    /// matching element. These only start above 0 when windows carry on across documents.
+    first_index: usize,
+    first_match: usize,
+}
+
+impl ActiveWindow {
+    fn new(depth: usize, selection: Selection, filtered: bool) -> Self {
+        Self {
+            depth,
+            selection,
+            filtered,
+            pending: None,
+            matched: 0,
+            positions: Vec::new(),
+            next: 0,
+            target: None,
+            first_index: 0,
+            first_match: 0,
         }
     }
 
-    pub fn current_key(&self) -> Option<OwnedJsonEvent> {
-        let index = self.keys_index;
-        if index < self.keys.len() {
-            Some(self.keys[index].clone())
+    fn start_element(&mut self, index: usize, rng: &mut ChaCha8Rng) {
+        if self.filtered {
+            self.pending = Some(Vec::new());
         } else {
-            None
+            self.selection.start_element(index, rng);
         }
     }
 
-    pub fn next_key(&mut self) -> Option<OwnedJsonEvent> {
-        if self.keys_index < self.keys.len() {
-            self.keys_index += 1;
-            self.current_key()
+    fn dropping(&self) -> bool {
+        self.pending.is_none() && self.selection.dropping()
+    }
+
+    fn capture(&mut self) -> Option<&mut Vec<OwnedJsonEvent>> {
+        match &mut self.pending {
+            Some(pending) => Some(pending),
+            None => self.selection.capture(),
+        }
+    }
+
+    /// Count the element that the selection saw at `index` as passed on. If elements were left out
+    /// since the last one that was passed on, returns how many were.
+    fn pass(&mut self, index: usize) -> Option<usize> {
+        let position = if self.filtered {
+            self.positions[index - self.first_match]
+        } else {
+            index - self.first_index
+        };
+        let left_out = position - self.next;
+        self.next = position + 1;
+        (left_out > 0).then_some(left_out)
+    }
+
+    /// Join up the elements that are being passed on, marking how many elements were left out
+    /// before each of them.
+    fn join(
+        &mut self,
+        elements: Vec<(usize, Vec<OwnedJsonEvent>)>,
+        annotation: Option<&Annotation>,
+    ) -> Vec<OwnedJsonEvent> {
+        let mut events = Vec::new();
+        for (index, element) in elements {
+            if let Some(annotation) = annotation {
+                if let Some(left_out) = self.pass(index) {
+                    events.extend(annotation.events(left_out));
+                }
+            }
+            events.extend(element);
+        }
+        events
+    }
+
+    /// The annotation for the elements of an array of `len` that were left out after the last
+    /// element that was passed on, if any were.
+    fn trailing(&self, len: usize, annotation: &Annotation) -> Vec<OwnedJsonEvent> {
+        let left_out = len - self.next;
+        if left_out > 0 {
+            annotation.events(left_out)
         } else {
-            None
+            Vec::new()
+        }
+    }
+}
+
+/// How the elements that were left out of an array are marked in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Annotation {
+    /// An object such as `{"$headj_elided": 10}`.
+    Object,
+    /// A string made from a template, with each `{}` replaced by the number of elements.
+    String(String),
+}
+
+impl Annotation {
+    /// The events of an element marking that `count` elements were left out.
+    fn events(&self, count: usize) -> Vec<OwnedJsonEvent> {
+        match self {
+            Self::Object => vec![
+                OwnedJsonEvent::StartObject,
+                OwnedJsonEvent::ObjectKey("$headj_elided".to_string()),
+                OwnedJsonEvent::Number(count.to_string()),
+                OwnedJsonEvent::EndObject,
+            ],
+            Self::String(template) => vec![OwnedJsonEvent::String(
+                template.replace("{}", &count.to_string()),
+            )],
         }
     }
 }
 
 pub struct CopySelector {
-    count: usize,
-    skip: usize,
+    targets: Vec<Target>,
+    found: Vec<bool>,
+    window: Window,
     no_context: bool,
-    json_file_state: JsonFileState,
+    rng: ChaCha8Rng,
+    path: Vec<Frame>,
+    windows: Vec<ActiveWindow>,
+    all_arrays: bool,
+    max_depth: Option<usize>,
+    predicate: Option<Predicate>,
+    projection: Option<Projection>,
+    /// Whether the projection or the context policy has left out the event currently being read.
+    hidden: bool,
+    context: ContextPolicy,
+    /// The number of containers open inside a part of the context that is being left out.
+    context_dropping: usize,
+    /// The key of a member of the context that is only copied if its value leads to a target.
+    context_key: Option<String>,
+    annotation: Option<Annotation>,
+    across_documents: bool,
+    wrap_started: bool,
+    queued: Vec<OwnedJsonEvent>,
 }
 
 impl CopySelector {
+    /// Create a selector for the array at the end of `keys`. If the key path ends with a slice, it
+    /// takes the place of `count` & `skip`.
     pub fn new(keys: KeyPath, count: usize, skip: usize, no_context: bool) -> Self {
-        let json_file_state = JsonFileState::new(keys);
         Self {
-            count,
-            skip,
+            targets: Vec::new(),
+            found: Vec::new(),
+            window: Window::Head {
+                skip,
+                count,
+                step: 1,
+            },
             no_context,
-            json_file_state,
+            rng: ChaCha8Rng::seed_from_u64(0),
+            path: Vec::new(),
+            windows: Vec::new(),
+            all_arrays: false,
+            max_depth: None,
+            predicate: None,
+            projection: None,
+            hidden: false,
+            context: ContextPolicy::Full,
+            context_dropping: 0,
+            context_key: None,
+            annotation: None,
+            across_documents: false,
+            wrap_started: false,
+            queued: Vec::new(),
         }
+        .with_target(keys)
     }
 
-    pub fn select(&mut self, event: JsonEvent) -> Result<bool> {
-        let state = &mut self.json_file_state;
-        let allow_context = !self.no_context;
-        match &state.target_placement {
-            TargetPlacement::Before => {
-                if let Some(current_key) = state.current_key() {
-                    // See if we're at a key that matches the current key
-                    if current_key.as_json_event() == event {
-                        let _ = state.next_key();
-                        return Ok(allow_context);
+    /// Also copy from the array at the end of `keys`, in the same pass.
+    pub fn with_target(mut self, keys: KeyPath) -> Self {
+        let window = keys.slice().map(Window::from_slice);
+        self.targets.push(Target {
+            keys,
+            window,
+            seen: 0,
+            matched: 0,
+        });
+        self.found.push(false);
+        self
+    }
+
+    /// Cut down every array in the document, instead of only those at the end of key paths. Arrays
+    /// inside more than `max_depth` containers are copied in full.
+    pub fn with_all_arrays(mut self, max_depth: Option<usize>) -> Self {
+        self.targets.clear();
+        self.found.clear();
+        self.all_arrays = true;
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Only select the elements of the target arrays that match `predicate`. Skipping, counting &
+    /// the other ways of selecting elements then only apply to the matching elements.
+    pub fn with_predicate(mut self, predicate: Predicate) -> Self {
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Cut down the elements of the outermost target arrays with `projection`. Elements are tested
+    /// against the predicate before they are projected.
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Cut down the context around the target arrays according to `context`.
+    pub fn with_context(mut self, context: ContextPolicy) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Mark where elements were left out of the target arrays, & of the context arrays that the
+    /// context policy cuts short, with `annotation`.
+    pub fn with_annotation(mut self, annotation: Annotation) -> Self {
+        self.annotation = Some(annotation);
+        self
+    }
+
+    /// In a stream of documents, carry each target's window on from one document to the next,
+    /// instead of starting it over, as if the target's arrays were all one array. Only windows that
+    /// decide on each element as it is read can carry on.
+    pub fn with_windows_across_documents(mut self) -> Result<Self> {
+        let streaming =
+            |window: &Window| matches!(window, Window::Head { .. } | Window::Bernoulli { .. });
+        if self.all_arrays
+            || !streaming(&self.window)
+            || self
+                .targets
+                .iter()
+                .any(|target| !target.window.as_ref().is_none_or(streaming))
+        {
+            return Err(eyre!(
+                "Only skipping, counting, stepping & sampling at a rate can carry on across documents"
+            ));
+        }
+        self.across_documents = true;
+        Ok(self)
+    }
+
+    /// Copy the elements of the target arrays that are in `slice`.
+    pub fn with_slice(mut self, slice: Slice) -> Self {
+        self.window = Window::from_slice(slice);
+        self
+    }
+
+    /// Copy only every `step`th element, starting with the first one after the skipped elements.
+    pub fn with_step(mut self, step: usize) -> Result<Self> {
+        if step == 0 {
+            return Err(eyre!("Step must be at least 1"));
+        }
+        if step != 1 {
+            self.check_not_all_sliced("--step")?;
+        }
+        if let Window::Head { step: s, .. } = &mut self.window {
+            *s = step;
+        }
+        Ok(self)
+    }
+
+    /// Fail if every target's key path ends with a slice, so that `option` would have no effect.
+    pub fn check_not_all_sliced(&self, option: &str) -> Result<()> {
+        let all_sliced = !self.targets.is_empty()
+            && self
+                .targets
+                .iter()
+                .all(|target| target.keys.slice().is_some());
+        if all_sliced {
+            return Err(eyre!(
+                "A slice at the end of the key path takes the place of {option}, so they cannot be combined"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fail if a target's key path ends with a slice, which would take the place of `option`.
+    fn check_no_slices(&self, option: &str) -> Result<()> {
+        match self
+            .targets
+            .iter()
+            .find(|target| target.keys.slice().is_some())
+        {
+            Some(target) => Err(eyre!(
+                "A slice in the key path \"{}\" cannot be combined with {option}",
+                target.keys.to_pointer()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Copy the last `count` elements of the target arrays, rather than the first.
+    pub fn with_tail(self, count: usize) -> Result<Self> {
+        self.check_no_slices("--tail")?;
+        Ok(self.with_slice(Slice {
+            start: SliceBound::FromEnd(count),
+            stop: None,
+            step: 1,
+        }))
+    }
+
+    /// Copy a random sample of `count` elements of the target arrays, keeping their order. The same
+    /// `seed` always selects the same elements.
+    pub fn with_sample(mut self, count: usize, seed: u64) -> Result<Self> {
+        self.check_no_slices("--sample")?;
+        self.window = Window::Reservoir {
+            skip: self.window.skip(),
+            count,
+        };
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        Ok(self)
+    }
+
+    /// Copy each element of the target arrays with probability `rate`. The same `seed` always
+    /// selects the same elements.
+    pub fn with_sample_rate(mut self, rate: f64, seed: u64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(eyre!("Sample rate must be between 0 and 1, found {rate}"));
+        }
+        self.check_no_slices("--sample-rate")?;
+        self.window = Window::Bernoulli {
+            skip: self.window.skip(),
+            rate,
+        };
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        Ok(self)
+    }
+
+    /// Without context, several target arrays are wrapped in an array of their own, so that the
+    /// output is still a single JSON document.
+    pub fn wraps_targets(&self) -> bool {
+        let many_arrays = self.targets.len() > 1
+            || self.all_arrays
+            || self
+                .targets
+                .iter()
+                .any(|target| target.keys.has_wildcards());
+        self.no_context && many_arrays
+    }
+
+    /// Add `state` to `states`, along with the states it also stands for: a `..` segment can match
+    /// no levels at all, so having reached one means having reached the segment after it too.
+    fn add_state(&self, states: &mut Vec<PathState>, mut state: PathState) {
+        let keys = &self.targets[state.target].keys;
+        loop {
+            if !states.contains(&state) {
+                states.push(state);
+            }
+            if keys.get(state.matched) != Some(&KeySegment::AnyDescendants) {
+                return;
+            }
+            state.matched += 1;
+        }
+    }
+
+    /// How far along each key path the root of the document is.
+    fn root_on_path(&self) -> Vec<PathState> {
+        let mut states = Vec::new();
+        for target in 0..self.targets.len() {
+            self.add_state(&mut states, PathState { target, matched: 0 });
+        }
+        states
+    }
+
+    /// How far along the key paths the container at `depth` is, based on its parent's member.
+    fn parent_on_path(&self, depth: usize) -> Vec<PathState> {
+        if depth < 2 {
+            self.root_on_path()
+        } else {
+            self.path[depth - 2].on_path.clone()
+        }
+    }
+
+    fn follow_segment(&mut self, matches: impl Fn(&KeySegment) -> bool) {
+        let depth = self.path.len();
+        let mut on_path = Vec::new();
+        for state in self.parent_on_path(depth) {
+            match self.targets[state.target].keys.get(state.matched) {
+                Some(KeySegment::AnyDescendants) => self.add_state(&mut on_path, state),
+                Some(segment) if matches(segment) => {
+                    let next = PathState {
+                        matched: state.matched + 1,
+                        ..state
+                    };
+                    self.add_state(&mut on_path, next);
+                }
+                _ => {}
+            }
+        }
+        if let Some(frame) = self.path.last_mut() {
+            frame.on_path = on_path;
+        }
+    }
+
+    /// The targets whose key paths lead to the value that is about to be read.
+    fn matching_targets(&self) -> Vec<usize> {
+        let on_path = match self.path.last() {
+            Some(frame) => frame.on_path.clone(),
+            None => self.root_on_path(),
+        };
+        let mut targets: Vec<usize> = on_path
+            .into_iter()
+            .filter(|state| self.targets[state.target].keys.len() == state.matched)
+            .map(|state| state.target)
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+
+    /// How to select the elements of the value that is about to be read at `depth`, if it is a
+    /// target array.
+    fn target_selection(
+        &mut self,
+        event: &JsonEvent,
+        depth: usize,
+    ) -> Result<Option<(Option<usize>, Selection)>> {
+        let is_array = *event == JsonEvent::StartArray;
+        let targets = self.matching_targets();
+        if let Some(&target) = targets.first() {
+            if !is_array {
+                // A wildcard may match values other than the arrays that are wanted.
+                if self.targets[target].keys.has_wildcards() {
+                    return Ok(None);
+                }
+                return Err(eyre!("Expecting Json array, found {event:?}"));
+            }
+            // Every target that leads here is copied, but the first one decides how
+            for &target in &targets {
+                self.found[target] = true;
+            }
+            let window = self.targets[target].window.as_ref().unwrap_or(&self.window);
+            return Ok(Some((Some(target), Selection::new(window))));
+        }
+        let within_depth = self.max_depth.is_none_or(|max_depth| depth <= max_depth);
+        let all_arrays = self.all_arrays && within_depth && is_array;
+        Ok(all_arrays.then(|| (None, Selection::new(&self.window))))
+    }
+
+    /// Leave out the context event that is about to be routed if the context policy says so,
+    /// queueing up a shortened version of it instead if there is one. `target` is whether the event
+    /// starts a target array.
+    fn trim_context(&mut self, event: &JsonEvent, target: bool) {
+        if self.no_context || !self.windows.is_empty() || self.context == ContextPolicy::Full {
+            return;
+        }
+        if self.context_dropping > 0 {
+            match event {
+                JsonEvent::StartArray | JsonEvent::StartObject => self.context_dropping += 1,
+                JsonEvent::EndArray | JsonEvent::EndObject => self.context_dropping -= 1,
+                _ => {}
+            }
+            self.hidden = true;
+            return;
+        }
+        let is_start = matches!(event, JsonEvent::StartArray | JsonEvent::StartObject);
+        match (self.context, event) {
+            (ContextPolicy::Summarize { elements, .. }, JsonEvent::EndArray) => {
+                if let (
+                    Some(annotation),
+                    Some(Frame {
+                        container: Container::Array(len),
+                        ..
+                    }),
+                ) = (&self.annotation, self.path.last())
+                {
+                    if *len > elements && self.parent_on_path(self.path.len()).is_empty() {
+                        self.queued.extend(annotation.events(len - elements));
                     }
-                } else if event == JsonEvent::StartArray {
-                    state.target_placement = TargetPlacement::Inside;
-                    return Ok(true);
-                } else {
-                    return Err(eyre!("Expecting Json array, found {event:?}"));
                 }
-                Ok(allow_context)
             }
-            TargetPlacement::Inside => {
-                if event == JsonEvent::EndArray && !state.in_sub_element() {
-                    state.target_placement = TargetPlacement::After;
-                    Ok(true)
+            (_, JsonEvent::EndArray | JsonEvent::EndObject) => {}
+            (ContextPolicy::PathOnly, JsonEvent::ObjectKey(key)) => {
+                // Whether the key is wanted depends on its value
+                self.hidden = true;
+                self.context_key = Some(key.to_string());
+            }
+            (ContextPolicy::PathOnly, _) => {
+                let on_path = self
+                    .path
+                    .last()
+                    .is_none_or(|frame| !frame.on_path.is_empty());
+                let key = self.context_key.take();
+                if target || (is_start && on_path) {
+                    self.queued.extend(key.map(OwnedJsonEvent::ObjectKey));
                 } else {
-                    // Perform the skip logic
-                    let index = state.target_index;
-                    let skipping = index < self.skip || index >= (self.count + self.skip);
-                    state.next_element(&event);
-                    Ok(!skipping)
+                    self.hidden = true;
+                    self.context_dropping = is_start.into();
                 }
             }
-            TargetPlacement::After => Ok(allow_context),
+            (ContextPolicy::Summarize { elements, .. }, _)
+                if matches!(
+                    self.path.last(),
+                    Some(Frame {
+                        container: Container::Array(next),
+                        ..
+                    }) if *next > elements
+                ) && self.parent_on_path(self.path.len()).is_empty() =>
+            {
+                self.hidden = true;
+                self.context_dropping = is_start.into();
+            }
+            (ContextPolicy::Summarize { string_len, .. }, JsonEvent::String(string))
+                if string.chars().count() > string_len =>
+            {
+                self.hidden = true;
+                let clipped: String = string.chars().take(string_len).collect();
+                self.queued
+                    .push(OwnedJsonEvent::String(format!("{clipped}\u{2026}")));
+            }
+            _ => {}
         }
     }
 
+    /// Decide whether an event inside the first `level` active windows is written out, holding it
+    /// back instead if one of those windows is capturing its current element. Outside of every
+    /// window, events are context, unless they are `in_target`.
+    fn route(&mut self, level: usize, event: JsonEvent, in_target: bool) -> bool {
+        if self.hidden {
+            return false;
+        }
+        let windows = &mut self.windows[..level];
+        if windows.iter().any(ActiveWindow::dropping) {
+            return false;
+        }
+        if let Some(capture) = windows.iter_mut().rev().find_map(ActiveWindow::capture) {
+            capture.push(event.into());
+            return false;
+        }
+        level > 0 || in_target || !self.no_context
+    }
+
+    /// Pass on elements that a window has released, as if they were being read now.
+    fn release(&mut self, level: usize, events: Vec<OwnedJsonEvent>) {
+        for event in events {
+            if self.route(level, event.as_json_event(), true) {
+                self.queued.push(event);
+            }
+        }
+    }
+
+    /// Called after a complete value has been read, in case it was an element of a target array.
+    fn end_value(&mut self) {
+        let depth = self.path.len();
+        let outermost = self.windows.len() == 1;
+        if let Some(window) = self.windows.last_mut() {
+            if window.depth == depth {
+                if let Some(Frame {
+                    container: Container::Array(next),
+                    ..
+                }) = self.path.last()
+                {
+                    let released = match window.pending.take() {
+                        None => window.selection.end_element(next - 1).into_iter().collect(),
+                        Some(mut element) => {
+                            if !self.predicate.as_ref().is_some_and(|p| p.matches(&element)) {
+                                return;
+                            }
+                            if let (true, Some(projection)) = (outermost, &mut self.projection) {
+                                element = projection.project_all(element);
+                            }
+                            // Only now does the selection see the element
+                            let index = window.matched;
+                            window.matched += 1;
+                            if self.annotation.is_some() {
+                                window.positions.push(next - 1);
+                            }
+                            window.selection.start_element(index, &mut self.rng);
+                            let mut passed = None;
+                            if !window.selection.dropping() {
+                                match window.selection.capture() {
+                                    Some(capture) => capture.extend(element),
+                                    None => passed = Some((index, element)),
+                                }
+                            }
+                            let mut released: Vec<_> =
+                                window.selection.end_element(index).into_iter().collect();
+                            released.extend(passed);
+                            released
+                        }
+                    };
+                    let released = window.join(released, self.annotation.as_ref());
+                    self.release(self.windows.len() - 1, released);
+                }
+            }
+        }
+    }
+
+    pub fn select(&mut self, event: JsonEvent) -> Result<bool> {
+        let depth = self.path.len();
+        let level = self.windows.len();
+        self.hidden = false;
+        // Filtered elements are only projected once they are known to match
+        let in_element = matches!(self.windows.first(), Some(w) if !w.filtered && depth >= w.depth);
+        if let (true, Some(projection)) = (in_element, &mut self.projection) {
+            match projection.project(event) {
+                Projected::Keep => {}
+                Projected::KeepAfterKey(key) => {
+                    let key = OwnedJsonEvent::ObjectKey(key);
+                    if self.route(level, key.as_json_event(), false) {
+                        self.queued.push(key);
+                    }
+                }
+                Projected::Drop => self.hidden = true,
+            }
+        }
+        match event {
+            JsonEvent::ObjectKey(key) => {
+                self.follow_segment(|segment| match segment {
+                    KeySegment::ObjectKey(k) => k == key,
+                    segment => segment == &KeySegment::AnyKey,
+                });
+                self.trim_context(&event, false);
+                Ok(self.route(level, event, false))
+            }
+            JsonEvent::EndArray | JsonEvent::EndObject => {
+                let copy = match self.windows.last() {
+                    Some(window) if window.depth == depth => {
+                        let mut window = self.windows.pop().unwrap();
+                        let array_len = match self.path.last() {
+                            Some(Frame {
+                                container: Container::Array(len),
+                                ..
+                            }) => *len,
+                            _ => 0,
+                        };
+                        let len = if window.filtered {
+                            window.matched
+                        } else {
+                            array_len
+                        };
+                        if let (true, Some(target)) = (self.across_documents, window.target) {
+                            self.targets[target].seen += array_len;
+                            self.targets[target].matched = window.matched;
+                        }
+                        let elements = window.selection.end_array(len);
+                        let mut released = window.join(elements, self.annotation.as_ref());
+                        if let Some(annotation) = &self.annotation {
+                            released.extend(window.trailing(array_len, annotation));
+                        }
+                        self.release(level - 1, released);
+                        self.route(level - 1, event, true)
+                    }
+                    _ => {
+                        self.trim_context(&event, false);
+                        self.route(level, event, false)
+                    }
+                };
+                let _ = self.path.pop();
+                self.end_value();
+                Ok(copy)
+            }
+            _ => {
+                if let Some(Frame {
+                    container: Container::Array(next),
+                    ..
+                }) = self.path.last_mut()
+                {
+                    let index = *next;
+                    *next += 1;
+                    self.follow_segment(|segment| {
+                        segment == &KeySegment::ArrayIndex(index)
+                            || segment == &KeySegment::AnyIndex
+                    });
+                    let mut marker = Vec::new();
+                    if let Some(window) = self.windows.last_mut() {
+                        if window.depth == depth {
+                            let index = window.first_index + index;
+                            window.start_element(index, &mut self.rng);
+                            // An element that is neither dropped nor held back is passed on now
+                            if let Some(annotation) = &self.annotation {
+                                if !window.dropping() && window.capture().is_none() {
+                                    if let Some(left_out) = window.pass(index) {
+                                        marker = annotation.events(left_out);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    for event in marker {
+                        if self.route(level, event.as_json_event(), false) {
+                            self.queued.push(event);
+                        }
+                    }
+                }
+                if let Some((target, selection)) = self.target_selection(&event, depth)? {
+                    self.trim_context(&event, true);
+                    let copy = self.route(level, event, true);
+                    if copy && level == 0 && self.wraps_targets() && !self.wrap_started {
+                        self.wrap_started = true;
+                        self.queued.push(OwnedJsonEvent::StartArray);
+                    }
+                    self.path.push(Frame::new(Container::Array(0)));
+                    let filtered = self.predicate.is_some();
+                    let mut window = ActiveWindow::new(depth + 1, selection, filtered);
+                    if let Some(target) = target {
+                        window.target = Some(target);
+                        window.first_index = self.targets[target].seen;
+                        window.first_match = self.targets[target].matched;
+                        window.matched = window.first_match;
+                    }
+                    self.windows.push(window);
+                    return Ok(copy);
+                }
+                self.trim_context(&event, false);
+                let copy = self.route(level, event, false);
+                match event {
+                    JsonEvent::StartArray => self.path.push(Frame::new(Container::Array(0))),
+                    JsonEvent::StartObject => self.path.push(Frame::new(Container::Object)),
+                    _ => self.end_value(),
+                }
+                Ok(copy)
+            }
+        }
+    }
+
+    /// Events that were held back by earlier calls to `select`, & that are now ready to be written.
+    /// They come before the event passed to the latest call to `select`.
+    pub fn drain_queued(&mut self) -> impl Iterator<Item = OwnedJsonEvent> + '_ {
+        self.queued.drain(..)
+    }
+
+    /// Get ready to read the next document in a stream, after `finish` has been called for the
+    /// last one.
+    pub fn next_document(&mut self) {
+        self.path.clear();
+        self.windows.clear();
+        self.found.fill(false);
+        self.wrap_started = false;
+        self.context_dropping = 0;
+        self.context_key = None;
+    }
+
     pub fn target_copied(&self) -> bool {
-        self.json_file_state.target_placement == TargetPlacement::After
+        self.windows.is_empty() && self.found.iter().all(|found| *found)
+    }
+
+    /// Called at the end of the document, to check that every target was copied & to queue up any
+    /// events that are still needed to complete the output.
+    pub fn finish(&mut self) -> Result<()> {
+        let missing: Vec<String> = self
+            .targets
+            .iter()
+            .zip(&self.found)
+            .filter(|(_, found)| !**found)
+            .map(|(target, _)| format!("\"{}\"", target.keys.to_pointer()))
+            .collect();
+        if !missing.is_empty() {
+            let cause = eyre!("No array found at {}", missing.join(", "));
+            return Err(cause.wrap_err("Did not complete JSON copy"));
+        }
+        if !self.target_copied() {
+            return Err(eyre!("Did not complete JSON copy"));
+        }
+        if self.wrap_started {
+            self.queued.push(OwnedJsonEvent::EndArray);
+        }
+        Ok(())
     }
 }
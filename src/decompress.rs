@@ -0,0 +1,230 @@
+use eyre::{eyre, Result};
+use log::warn;
+use std::io::{BufRead, Cursor, Read};
+use std::path::Path;
+
+/// How the input is compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+/// The magic bytes that each compressed format starts with.
+const MAGIC: [(Compression, &[u8]); 4] = [
+    (Compression::Gzip, &[0x1F, 0x8B]),
+    (Compression::Zstd, &[0x28, 0xB5, 0x2F, 0xFD]),
+    (Compression::Bzip2, b"BZh"),
+    (Compression::Xz, &[0xFD, b'7', b'z', b'X', b'Z', 0x00]),
+];
+
+/// The file extensions of each compressed format, which are only used to warn when the input
+/// doesn't match its name.
+const EXTENSIONS: [(Compression, &str); 6] = [
+    (Compression::Gzip, "gz"),
+    (Compression::Gzip, "gzip"),
+    (Compression::Zstd, "zst"),
+    (Compression::Zstd, "zstd"),
+    (Compression::Bzip2, "bz2"),
+    (Compression::Xz, "xz"),
+];
+
+impl Compression {
+    /// Work out the compression from the first bytes of the input.
+    pub fn detect(head: &[u8]) -> Self {
+        MAGIC
+            .iter()
+            .find(|(_, magic)| head.starts_with(magic))
+            .map_or(Self::None, |(compression, _)| *compression)
+    }
+
+    /// The compression that the extension of `path` suggests.
+    pub fn from_extension(path: &Path) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str());
+        extension
+            .and_then(|extension| {
+                EXTENSIONS
+                    .iter()
+                    .find(|(_, e)| extension.eq_ignore_ascii_case(e))
+            })
+            .map_or(Self::None, |(compression, _)| *compression)
+    }
+
+    /// The cargo feature that reading this compression needs.
+    fn feature(self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+        }
+    }
+}
+
+/// Wrap `reader` in a streaming decoder if the input starts like a compressed format. `path` is the
+/// file it reads from, if any, whose extension is only checked against the input.
+pub fn decompress<'a>(
+    mut reader: Box<dyn BufRead + 'a>,
+    path: Option<&Path>,
+) -> Result<Box<dyn BufRead + 'a>> {
+    // A single read may not hold all of the magic bytes, so read them before looking
+    let mut head = Vec::new();
+    reader.as_mut().take(6).read_to_end(&mut head)?;
+    let compression = Compression::detect(&head);
+    if let Some(path) = path {
+        let named = Compression::from_extension(path);
+        if named != Compression::None && named != compression {
+            let actual = match compression {
+                Compression::None => "plain JSON".to_string(),
+                compression => format!("{compression:?}"),
+            };
+            warn!(
+                "\"{}\" is named like a {named:?} file, but is read as {actual} from its first bytes",
+                path.display()
+            );
+        }
+    }
+    let reader = Cursor::new(head).chain(reader);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(std::io::BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        )),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(std::io::BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(reader)?,
+        )),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Box::new(std::io::BufReader::new(
+            bzip2::bufread::MultiBzDecoder::new(reader),
+        )),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Box::new(std::io::BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(reader),
+        )),
+        #[allow(unreachable_patterns)]
+        compression => {
+            return Err(eyre!(
+                "The input is {compression:?} compressed, but headj was built without the \"{}\" feature",
+                compression.feature()
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decompress::{decompress, Compression};
+    use std::io::{BufReader, Cursor, Read};
+    use std::path::Path;
+
+    fn read_all(input: Vec<u8>, path: Option<&Path>) -> String {
+        let mut reader = decompress(Box::new(BufReader::new(Cursor::new(input))), path).unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::Gzip, Compression::detect(b"\x1f\x8b\x08"));
+        assert_eq!(Compression::Bzip2, Compression::detect(b"BZh91AY"));
+        assert_eq!(Compression::Xz, Compression::detect(b"\xfd7zXZ\x00"));
+        assert_eq!(Compression::None, Compression::detect(b"[1,2]"));
+        assert_eq!(Compression::None, Compression::detect(b"\x1f"));
+        assert_eq!(
+            Compression::Zstd,
+            Compression::from_extension(Path::new("dump.json.ZST"))
+        );
+        assert_eq!(
+            Compression::None,
+            Compression::from_extension(Path::new("a.json"))
+        );
+    }
+
+    #[test]
+    fn test_decompress() {
+        assert_eq!("[1,2]", read_all(b"[1,2]".to_vec(), None));
+        assert_eq!("", read_all(Vec::new(), None));
+        // The magic bytes decide, whatever the file is called
+        let path = Path::new("data.json.gz");
+        assert_eq!("[1,2]", read_all(b"[1,2]".to_vec(), Some(path)));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_decompress_gzip() {
+        use flate2::{write::GzEncoder, Compression as Level};
+        use std::io::Write;
+
+        // Concatenated members, as from `cat a.gz b.gz`, are read as one stream
+        let mut input = Vec::new();
+        for part in ["[1,", "2]"] {
+            let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            input.extend(encoder.finish().unwrap());
+        }
+        assert_eq!("[1,2]", read_all(input, None));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_decompress_zstd() {
+        // Concatenated frames are read as one stream
+        let mut input = Vec::new();
+        for part in ["[1,", "2]"] {
+            input.extend(zstd::stream::encode_all(part.as_bytes(), 0).unwrap());
+        }
+        assert_eq!("[1,2]", read_all(input, None));
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn test_decompress_bzip2() {
+        use bzip2::{write::BzEncoder, Compression as Level};
+        use std::io::Write;
+
+        // Concatenated streams, as from `cat a.bz2 b.bz2`, are read as one stream
+        let mut input = Vec::new();
+        for part in ["[1,", "2]"] {
+            let mut encoder = BzEncoder::new(Vec::new(), Level::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            input.extend(encoder.finish().unwrap());
+        }
+        assert_eq!("[1,2]", read_all(input, None));
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn test_decompress_xz() {
+        use std::io::Write;
+        use xz2::write::XzEncoder;
+
+        // Concatenated streams, as from `cat a.xz b.xz`, are read as one stream
+        let mut input = Vec::new();
+        for part in ["[1,", "2]"] {
+            let mut encoder = XzEncoder::new(Vec::new(), 6);
+            encoder.write_all(part.as_bytes()).unwrap();
+            input.extend(encoder.finish().unwrap());
+        }
+        assert_eq!("[1,2]", read_all(input, None));
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_decompress_without_feature() {
+        let input = b"\x1f\x8b\x08\x00".to_vec();
+        let e = decompress(Box::new(BufReader::new(Cursor::new(input))), None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            "The input is Gzip compressed, but headj was built without the \"gzip\" feature",
+            e.to_string()
+        );
+    }
+}
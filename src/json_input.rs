@@ -0,0 +1,324 @@
+use crate::key_path::OwnedJsonEvent;
+use eyre::Result;
+use json_event_parser::{JsonEvent, JsonReader};
+use log::warn;
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+/// How the JSON that is read in is laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    /// A single JSON document.
+    #[default]
+    Json,
+    /// One JSON value per line, read as if the lines were the elements of an array.
+    Ndjson,
+    /// An RFC 7464 JSON text sequence, read as if its records were the elements of an array.
+    JsonSeq,
+    /// Any number of JSON documents one after another, each read on its own, up to a limit.
+    Documents(Option<usize>),
+    /// An RFC 7464 JSON text sequence with each record read as a document, up to a limit.
+    JsonSeqDocuments(Option<usize>),
+}
+
+/// How the records of the input are told apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Each record is a line, as in newline delimited JSON.
+    Lines,
+    /// Each record starts with an ASCII record separator, as in a JSON text sequence.
+    Sequence,
+}
+
+/// The ASCII record separator that starts each record of a JSON text sequence.
+pub const RECORD_SEPARATOR: u8 = 0x1E;
+
+/// Something that JSON events can be read from, one at a time.
+pub trait EventReader {
+    fn read_event<'a>(&'a mut self, buffer: &'a mut Vec<u8>) -> Result<JsonEvent<'a>>;
+}
+
+impl<R: BufRead> EventReader for JsonReader<R> {
+    fn read_event<'a>(&'a mut self, buffer: &'a mut Vec<u8>) -> Result<JsonEvent<'a>> {
+        Ok(JsonReader::read_event(self, buffer)?)
+    }
+}
+
+/// Reads a single document from a stream of them, stopping as soon as its root value ends.
+pub struct DocumentReader<R: BufRead> {
+    reader: JsonReader<R>,
+    depth: usize,
+    finished: bool,
+}
+
+impl<R: BufRead> DocumentReader<R> {
+    pub fn new(in_json: R) -> Self {
+        Self {
+            reader: JsonReader::from_reader(in_json),
+            depth: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<R: BufRead> EventReader for DocumentReader<R> {
+    fn read_event<'a>(&'a mut self, buffer: &'a mut Vec<u8>) -> Result<JsonEvent<'a>> {
+        if self.finished {
+            return Ok(JsonEvent::Eof);
+        }
+        // Reading on past the root value would run into the next document
+        let event = self.reader.read_event(buffer)?;
+        match event {
+            JsonEvent::StartArray | JsonEvent::StartObject => self.depth += 1,
+            JsonEvent::EndArray | JsonEvent::EndObject => self.depth -= 1,
+            _ => {}
+        }
+        self.finished = self.depth == 0 && !matches!(event, JsonEvent::ObjectKey(_));
+        Ok(event)
+    }
+}
+
+/// Skip the whitespace before the next document in a stream, returning whether there is one.
+pub fn next_document<R: BufRead>(in_json: &mut R) -> Result<bool> {
+    loop {
+        let buffer = in_json.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(false);
+        }
+        let whitespace = buffer
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+        let found = whitespace < buffer.len();
+        in_json.consume(whitespace);
+        if found {
+            return Ok(true);
+        }
+    }
+}
+
+/// Events that have already been read, to be read again one at a time.
+pub struct BufferedEvents {
+    events: VecDeque<OwnedJsonEvent>,
+    /// The event that was read last, which the returned `JsonEvent` borrows from.
+    event: OwnedJsonEvent,
+}
+
+impl BufferedEvents {
+    pub fn new(events: VecDeque<OwnedJsonEvent>) -> Self {
+        Self {
+            events,
+            event: OwnedJsonEvent::Eof,
+        }
+    }
+}
+
+impl EventReader for BufferedEvents {
+    fn read_event<'a>(&'a mut self, _buffer: &'a mut Vec<u8>) -> Result<JsonEvent<'a>> {
+        self.event = self.events.pop_front().unwrap_or(OwnedJsonEvent::Eof);
+        Ok(self.event.as_json_event())
+    }
+}
+
+/// Reads input made up of records that each hold a JSON value. Blank records are ignored, &
+/// records that aren't valid JSON are left out with a warning.
+///
+/// As an `EventReader`, it reads the records as the elements of an array.
+pub struct RecordReader<R: BufRead> {
+    records: R,
+    framing: Framing,
+    record: Vec<u8>,
+    /// The number of the line or record that was read last.
+    number: usize,
+    /// Whether the text read last ended with a record separator, so that a record follows it.
+    after_separator: bool,
+    elements: BufferedEvents,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(records: R, framing: Framing) -> Self {
+        Self {
+            records,
+            framing,
+            record: Vec::new(),
+            number: 0,
+            after_separator: false,
+            elements: BufferedEvents::new(VecDeque::new()),
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Read the events of the next valid record, if there is one.
+    pub fn next_record(&mut self) -> Result<Option<VecDeque<OwnedJsonEvent>>> {
+        let delimiter = match self.framing {
+            Framing::Lines => b'\n',
+            Framing::Sequence => RECORD_SEPARATOR,
+        };
+        loop {
+            self.record.clear();
+            if self.records.read_until(delimiter, &mut self.record)? == 0 {
+                return Ok(None);
+            }
+            let in_record = self.framing == Framing::Lines || self.after_separator;
+            self.after_separator = self.record.last() == Some(&delimiter);
+            if self.after_separator {
+                let _ = self.record.pop();
+            }
+            let blank = self.record.iter().all(u8::is_ascii_whitespace);
+            if !in_record {
+                if !blank {
+                    warn!("Skipping text before the first record separator");
+                }
+                continue;
+            }
+            self.number += 1;
+            if blank {
+                continue;
+            }
+            match (read_record(&self.record), self.framing) {
+                (Err(e), Framing::Lines) => warn!("Skipping line {}: {e}", self.number),
+                (Err(e), Framing::Sequence) => warn!("Skipping record {}: {e}", self.number),
+                (Ok(events), Framing::Sequence) if self.truncated(&events) => {
+                    warn!("Skipping record {}: it was cut short", self.number)
+                }
+                (Ok(events), _) => return Ok(Some(events)),
+            }
+        }
+    }
+
+    /// Whether a record is a number, `true`, `false` or `null` that may have been cut short,
+    /// because it isn't followed by whitespace.
+    fn truncated(&self, events: &VecDeque<OwnedJsonEvent>) -> bool {
+        let scalar = matches!(
+            events.front(),
+            Some(OwnedJsonEvent::Number(_) | OwnedJsonEvent::Boolean(_) | OwnedJsonEvent::Null)
+        );
+        scalar && !self.record.last().is_some_and(u8::is_ascii_whitespace)
+    }
+}
+
+/// Read all of the events of the single JSON value in `record`.
+fn read_record(record: &[u8]) -> Result<VecDeque<OwnedJsonEvent>> {
+    let mut reader = JsonReader::from_reader(record);
+    let mut buffer = Vec::new();
+    let mut events = VecDeque::new();
+    loop {
+        match reader.read_event(&mut buffer)? {
+            JsonEvent::Eof => return Ok(events),
+            event => events.push_back(event.into()),
+        }
+    }
+}
+
+impl<R: BufRead> EventReader for RecordReader<R> {
+    fn read_event<'a>(&'a mut self, buffer: &'a mut Vec<u8>) -> Result<JsonEvent<'a>> {
+        if !self.started {
+            self.started = true;
+            return Ok(JsonEvent::StartArray);
+        }
+        while self.elements.events.is_empty() {
+            if self.finished {
+                return Ok(JsonEvent::Eof);
+            }
+            match self.next_record()? {
+                Some(events) => self.elements.events = events,
+                None => {
+                    self.finished = true;
+                    return Ok(JsonEvent::EndArray);
+                }
+            }
+        }
+        self.elements.read_event(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json_input::{next_document, DocumentReader, EventReader, Framing, RecordReader};
+    use crate::key_path::OwnedJsonEvent;
+    use json_event_parser::JsonEvent;
+
+    fn read_all(reader: &mut dyn EventReader) -> Vec<OwnedJsonEvent> {
+        let mut buffer = Vec::new();
+        let mut events = Vec::new();
+        loop {
+            match reader.read_event(&mut buffer).unwrap() {
+                JsonEvent::Eof => return events,
+                event => events.push(event.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let input = "{\"a\":1}\n\n[2]\r\n{\"a\":\nnull\n3 4\n\"x\"";
+        let events = read_all(&mut RecordReader::new(input.as_bytes(), Framing::Lines));
+        assert_eq!(
+            vec![
+                OwnedJsonEvent::StartArray,
+                OwnedJsonEvent::StartObject,
+                OwnedJsonEvent::ObjectKey("a".to_string()),
+                OwnedJsonEvent::Number("1".to_string()),
+                OwnedJsonEvent::EndObject,
+                OwnedJsonEvent::StartArray,
+                OwnedJsonEvent::Number("2".to_string()),
+                OwnedJsonEvent::EndArray,
+                OwnedJsonEvent::Null,
+                OwnedJsonEvent::String("x".to_string()),
+                OwnedJsonEvent::EndArray,
+            ],
+            events
+        );
+        assert_eq!(
+            vec![OwnedJsonEvent::StartArray, OwnedJsonEvent::EndArray],
+            read_all(&mut RecordReader::new("".as_bytes(), Framing::Lines))
+        );
+    }
+
+    #[test]
+    fn test_documents() {
+        let mut input = " {\"a\":[1]}[2]\n3 \"x\"\n\n".as_bytes();
+        let mut documents = Vec::new();
+        while next_document(&mut input).unwrap() {
+            documents.push(read_all(&mut DocumentReader::new(&mut input)));
+        }
+        assert_eq!(4, documents.len());
+        assert_eq!(
+            vec![
+                OwnedJsonEvent::StartArray,
+                OwnedJsonEvent::Number("2".to_string()),
+                OwnedJsonEvent::EndArray
+            ],
+            documents[1]
+        );
+        assert_eq!(vec![OwnedJsonEvent::Number("3".to_string())], documents[2]);
+        assert_eq!(vec![OwnedJsonEvent::String("x".to_string())], documents[3]);
+    }
+
+    #[test]
+    fn test_json_seq() {
+        let input = "x\x1e{\"a\":1}\n\x1e\x1e[2\n\x1e3\n\x1e4\x1e\"five\"\n";
+        let mut reader = RecordReader::new(input.as_bytes(), Framing::Sequence);
+        let mut records = Vec::new();
+        while let Some(events) = reader.next_record().unwrap() {
+            records.push(events.into_iter().collect::<Vec<_>>());
+        }
+        assert_eq!(
+            vec![
+                vec![
+                    OwnedJsonEvent::StartObject,
+                    OwnedJsonEvent::ObjectKey("a".to_string()),
+                    OwnedJsonEvent::Number("1".to_string()),
+                    OwnedJsonEvent::EndObject,
+                ],
+                vec![OwnedJsonEvent::Number("3".to_string())],
+                vec![OwnedJsonEvent::String("five".to_string())],
+            ],
+            records
+        );
+        assert_eq!(6, reader.number);
+    }
+}
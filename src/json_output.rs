@@ -0,0 +1,469 @@
+use crate::json_input::RECORD_SEPARATOR;
+use eyre::{eyre, Report};
+use json_event_parser::{JsonEvent, JsonWriter};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::str::FromStr;
+
+/// How each level of nesting is indented in formatted output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+impl FromStr for Indent {
+    type Err = Report;
+
+    /// Parse either a number of spaces or the word `tab`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("tab") || s == "\t" {
+            Ok(Self::Tab)
+        } else {
+            s.parse()
+                .map(Self::Spaces)
+                .map_err(|_| eyre!("Expecting a number of spaces or \"tab\", found \"{s}\""))
+        }
+    }
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Self::Spaces(2)
+    }
+}
+
+/// What is written between the elements of line based output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Separator(String);
+
+impl FromStr for Separator {
+    type Err = Report;
+
+    /// Parse a separator, where `\0`, `\n`, `\r`, `\t`, `\\` & `\xHH` stand for the characters
+    /// they do in Rust.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut separator = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                separator.push(c);
+                continue;
+            }
+            separator.push(match chars.next() {
+                Some('0') => '\0',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('\\') => '\\',
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    u8::from_str_radix(&hex, 16)
+                        .ok()
+                        .filter(u8::is_ascii)
+                        .map(char::from)
+                        .ok_or_else(|| {
+                            eyre!("Expecting 2 hex digits up to 7F after \"\\x\", found \"{hex}\"")
+                        })?
+                }
+                c => return Err(eyre!("Unknown escape in separator \"{s}\": {c:?}")),
+            });
+        }
+        Ok(Self(separator))
+    }
+}
+
+impl Default for Separator {
+    fn default() -> Self {
+        Self("\n".to_string())
+    }
+}
+
+/// The kind of output that is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OutputKind {
+    /// A JSON document.
+    #[default]
+    Json,
+    /// Each element of the target arrays on its own line, as newline delimited JSON.
+    Ndjson,
+    /// Each element of the target arrays as a record of an RFC 7464 JSON text sequence.
+    JsonSeq,
+}
+
+impl FromStr for OutputKind {
+    type Err = Report;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("json") {
+            Ok(Self::Json)
+        } else if s.eq_ignore_ascii_case("ndjson") || s.eq_ignore_ascii_case("jsonl") {
+            Ok(Self::Ndjson)
+        } else if s.eq_ignore_ascii_case("json-seq") {
+            Ok(Self::JsonSeq)
+        } else {
+            Err(eyre!(
+                "Expecting \"json\", \"ndjson\" or \"json-seq\", found \"{s}\""
+            ))
+        }
+    }
+}
+
+/// The layout of the JSON that is written out.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// No whitespace at all.
+    #[default]
+    Compact,
+    /// One member or element per line, indented to show nesting.
+    Pretty(Indent),
+    /// Each element without any whitespace, followed by the separator, & nothing around them.
+    Lines(Separator),
+    /// Each element as a record of a JSON text sequence, & nothing around them.
+    Sequence,
+}
+
+/// Something that JSON events can be written to, one at a time.
+pub trait EventWriter {
+    fn write_event(&mut self, event: JsonEvent<'_>) -> Result<()>;
+}
+
+impl<W: Write> EventWriter for JsonWriter<W> {
+    fn write_event(&mut self, event: JsonEvent<'_>) -> Result<()> {
+        JsonWriter::write_event(self, event)
+    }
+}
+
+/// A streaming JSON writer that puts every member & element on its own line.
+///
+/// Empty arrays & objects are written as `[]` & `{}`, and the output ends with a newline.
+pub struct PrettyJsonWriter<W: Write> {
+    writer: W,
+    indent: Indent,
+    /// For each open container, whether anything has been written into it yet.
+    non_empty: Vec<bool>,
+    after_key: bool,
+}
+
+impl<W: Write> PrettyJsonWriter<W> {
+    pub fn new(writer: W, indent: Indent) -> Self {
+        Self {
+            writer,
+            indent,
+            non_empty: Vec::new(),
+            after_key: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_newline(&mut self) -> Result<()> {
+        self.writer.write_all(b"\n")?;
+        for _ in 0..self.non_empty.len() {
+            match self.indent {
+                Indent::Spaces(width) => write!(self.writer, "{:width$}", "")?,
+                Indent::Tab => self.writer.write_all(b"\t")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn before_member(&mut self) -> Result<()> {
+        match self.non_empty.last_mut() {
+            Some(non_empty) => {
+                let separator = *non_empty;
+                *non_empty = true;
+                if separator {
+                    self.writer.write_all(b",")?;
+                }
+                self.write_newline()
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn before_value(&mut self) -> Result<()> {
+        if self.after_key {
+            self.after_key = false;
+            Ok(())
+        } else {
+            self.before_member()
+        }
+    }
+
+    fn after_value(&mut self) -> Result<()> {
+        if self.non_empty.is_empty() {
+            self.writer.write_all(b"\n")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_scalar(&mut self, token: &[u8]) -> Result<()> {
+        self.before_value()?;
+        self.writer.write_all(token)?;
+        self.after_value()
+    }
+
+    fn write_end(&mut self, bracket: &[u8]) -> Result<()> {
+        match self.non_empty.pop() {
+            Some(non_empty) => {
+                if non_empty {
+                    self.write_newline()?;
+                }
+                self.writer.write_all(bracket)?;
+                self.after_value()
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Closing a container that was not opened",
+            )),
+        }
+    }
+}
+
+impl<W: Write> EventWriter for PrettyJsonWriter<W> {
+    fn write_event(&mut self, event: JsonEvent<'_>) -> Result<()> {
+        match event {
+            JsonEvent::String(s) => {
+                self.before_value()?;
+                write_escaped_json_string(s, &mut self.writer)?;
+                self.after_value()
+            }
+            JsonEvent::Number(number) => self.write_scalar(number.as_bytes()),
+            JsonEvent::Boolean(b) => self.write_scalar(if b { b"true" } else { b"false" }),
+            JsonEvent::Null => self.write_scalar(b"null"),
+            JsonEvent::StartArray | JsonEvent::StartObject => {
+                self.before_value()?;
+                self.non_empty.push(false);
+                let bracket = if event == JsonEvent::StartArray {
+                    b"["
+                } else {
+                    b"{"
+                };
+                self.writer.write_all(bracket)
+            }
+            JsonEvent::EndArray => self.write_end(b"]"),
+            JsonEvent::EndObject => self.write_end(b"}"),
+            JsonEvent::ObjectKey(key) => {
+                self.before_member()?;
+                write_escaped_json_string(key, &mut self.writer)?;
+                self.after_key = true;
+                self.writer.write_all(b": ")
+            }
+            JsonEvent::Eof => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "EOF is not allowed in JSON writer",
+            )),
+        }
+    }
+}
+
+/// Writes each value that is `element_depth` containers deep without any whitespace, followed by a
+/// separator, & leaves out everything around those values.
+pub struct LinesWriter<W: Write> {
+    /// The writer, when no element is being written.
+    writer: Option<W>,
+    element: Option<JsonWriter<W>>,
+    /// What is written before each element.
+    prefix: &'static [u8],
+    separator: Separator,
+    element_depth: usize,
+    depth: usize,
+}
+
+impl<W: Write> LinesWriter<W> {
+    pub fn new(writer: W, separator: Separator, element_depth: usize) -> Self {
+        Self {
+            writer: Some(writer),
+            element: None,
+            prefix: b"",
+            separator,
+            element_depth,
+            depth: 0,
+        }
+    }
+
+    /// Write the elements as the records of a JSON text sequence, each starting with a record
+    /// separator & ending with a newline.
+    pub fn json_seq(writer: W, element_depth: usize) -> Self {
+        Self {
+            prefix: &[RECORD_SEPARATOR],
+            ..Self::new(writer, Separator::default(), element_depth)
+        }
+    }
+}
+
+impl<W: Write> EventWriter for LinesWriter<W> {
+    fn write_event(&mut self, event: JsonEvent<'_>) -> Result<()> {
+        if matches!(event, JsonEvent::EndArray | JsonEvent::EndObject) {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        let in_element = self.depth >= self.element_depth;
+        if in_element {
+            if self.element.is_none() {
+                if let Some(writer) = &mut self.writer {
+                    writer.write_all(self.prefix)?;
+                }
+                self.element = self.writer.take().map(JsonWriter::from_writer);
+            }
+            if let Some(element) = &mut self.element {
+                element.write_event(event)?;
+            }
+        }
+        if matches!(event, JsonEvent::StartArray | JsonEvent::StartObject) {
+            self.depth += 1;
+        }
+        if in_element && self.depth == self.element_depth {
+            if let Some(element) = self.element.take() {
+                let mut writer = element.into_inner();
+                writer.write_all(self.separator.0.as_bytes())?;
+                self.writer = Some(writer);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_escaped_json_string(s: &str, sink: &mut impl Write) -> Result<()> {
+    sink.write_all(b"\"")?;
+    let mut buffer = [0; 4];
+    for c in s.chars() {
+        match c {
+            '\\' => sink.write_all(b"\\\\"),
+            '"' => sink.write_all(b"\\\""),
+            '\u{08}' => sink.write_all(b"\\b"),
+            '\u{0C}' => sink.write_all(b"\\f"),
+            '\n' => sink.write_all(b"\\n"),
+            '\r' => sink.write_all(b"\\r"),
+            '\t' => sink.write_all(b"\\t"),
+            c if c < char::from(32) => write!(sink, "\\u{:04X}", c as u32),
+            c => sink.write_all(c.encode_utf8(&mut buffer).as_bytes()),
+        }?;
+    }
+    sink.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json_output::{EventWriter, Indent, LinesWriter, PrettyJsonWriter, Separator};
+    use json_event_parser::JsonEvent;
+    use std::str::FromStr;
+
+    fn pretty(events: &[JsonEvent], indent: Indent) -> String {
+        let mut writer = PrettyJsonWriter::new(Vec::new(), indent);
+        for event in events {
+            writer.write_event(*event).unwrap();
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_indent_from_str() {
+        assert_eq!(Indent::Spaces(4), Indent::from_str("4").unwrap());
+        assert_eq!(Indent::Tab, Indent::from_str("tab").unwrap());
+        assert!(Indent::from_str("wide").is_err());
+    }
+
+    #[test]
+    fn test_pretty_nested() {
+        let events = [
+            JsonEvent::StartObject,
+            JsonEvent::ObjectKey("a"),
+            JsonEvent::Number("1"),
+            JsonEvent::ObjectKey("b"),
+            JsonEvent::StartArray,
+            JsonEvent::Boolean(true),
+            JsonEvent::Null,
+            JsonEvent::EndArray,
+            JsonEvent::EndObject,
+        ];
+        assert_eq!(
+            "{\n  \"a\": 1,\n  \"b\": [\n    true,\n    null\n  ]\n}\n",
+            pretty(&events, Indent::default())
+        );
+        assert_eq!(
+            "{\n\t\"a\": 1,\n\t\"b\": [\n\t\ttrue,\n\t\tnull\n\t]\n}\n",
+            pretty(&events, Indent::Tab)
+        );
+    }
+
+    #[test]
+    fn test_pretty_empty_containers() {
+        let events = [
+            JsonEvent::StartArray,
+            JsonEvent::StartObject,
+            JsonEvent::EndObject,
+            JsonEvent::StartArray,
+            JsonEvent::EndArray,
+            JsonEvent::EndArray,
+        ];
+        assert_eq!("[\n {},\n []\n]\n", pretty(&events, Indent::Spaces(1)));
+    }
+
+    #[test]
+    fn test_pretty_escapes() {
+        let events = [JsonEvent::String("a\"b\\c\n\u{1}")];
+        assert_eq!(
+            "\"a\\\"b\\\\c\\n\\u0001\"\n",
+            pretty(&events, Indent::default())
+        );
+    }
+
+    #[test]
+    fn test_separator_from_str() {
+        assert_eq!(Separator::default(), Separator::from_str("\\n").unwrap());
+        assert_eq!(
+            Separator("\0;\t\\\u{1e}".to_string()),
+            Separator::from_str("\\0;\\t\\\\\\x1e").unwrap()
+        );
+        assert!(Separator::from_str("\\q").is_err());
+        assert!(Separator::from_str("\\xff").is_err());
+    }
+
+    #[test]
+    fn test_lines() {
+        let events = [
+            JsonEvent::StartArray,
+            JsonEvent::StartObject,
+            JsonEvent::ObjectKey("a"),
+            JsonEvent::StartArray,
+            JsonEvent::Number("1"),
+            JsonEvent::EndArray,
+            JsonEvent::EndObject,
+            JsonEvent::String("b"),
+            JsonEvent::EndArray,
+        ];
+        let mut writer = LinesWriter::new(Vec::new(), Separator::default(), 1);
+        for event in events {
+            writer.write_event(event).unwrap();
+        }
+        assert_eq!(
+            "{\"a\":[1]}\n\"b\"\n",
+            String::from_utf8(writer.writer.unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_seq() {
+        let mut writer = LinesWriter::json_seq(Vec::new(), 2);
+        let events = [
+            JsonEvent::StartObject,
+            JsonEvent::ObjectKey("a"),
+            JsonEvent::StartArray,
+            JsonEvent::Number("1"),
+            JsonEvent::StartArray,
+            JsonEvent::EndArray,
+            JsonEvent::EndArray,
+            JsonEvent::EndObject,
+        ];
+        for event in events {
+            writer.write_event(event).unwrap();
+        }
+        assert_eq!(
+            "\u{1e}1\n\u{1e}[]\n",
+            String::from_utf8(writer.writer.unwrap()).unwrap()
+        );
+    }
+}
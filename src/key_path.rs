@@ -1,7 +1,8 @@
-use eyre::Result;
+use eyre::{eyre, Result};
 use json_event_parser::JsonEvent;
 use std::iter::Iterator;
 use std::ops::Index;
+use std::str::Chars;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum OwnedJsonEvent {
@@ -18,7 +19,7 @@ pub enum OwnedJsonEvent {
 }
 
 impl OwnedJsonEvent {
-    pub fn as_json_event(&self) -> JsonEvent {
+    pub fn as_json_event(&self) -> JsonEvent<'_> {
         match self {
             Self::String(s) => JsonEvent::String(s),
             Self::Number(s) => JsonEvent::Number(s),
@@ -34,52 +35,429 @@ impl OwnedJsonEvent {
     }
 }
 
-#[derive(Default)]
+impl From<JsonEvent<'_>> for OwnedJsonEvent {
+    fn from(event: JsonEvent<'_>) -> Self {
+        match event {
+            JsonEvent::String(s) => Self::String(s.to_string()),
+            JsonEvent::Number(s) => Self::Number(s.to_string()),
+            JsonEvent::ObjectKey(s) => Self::ObjectKey(s.to_string()),
+            JsonEvent::Boolean(b) => Self::Boolean(b),
+            JsonEvent::Null => Self::Null,
+            JsonEvent::StartArray => Self::StartArray,
+            JsonEvent::EndArray => Self::EndArray,
+            JsonEvent::StartObject => Self::StartObject,
+            JsonEvent::EndObject => Self::EndObject,
+            JsonEvent::Eof => Self::Eof,
+        }
+    }
+}
+
+/// One step along a `KeyPath`: either the key of an object member or the index of an array element.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeySegment {
+    ObjectKey(String),
+    ArrayIndex(usize),
+    /// `*`, which matches any key of an object.
+    AnyKey,
+    /// `[*]`, which matches any element of an array.
+    AnyIndex,
+    /// `..`, which skips over any number of levels, including none, to reach the next segment.
+    AnyDescendants,
+}
+
+impl KeySegment {
+    pub fn is_wildcard(&self) -> bool {
+        matches!(self, Self::AnyKey | Self::AnyIndex | Self::AnyDescendants)
+    }
+}
+
+/// One end of a `Slice`, counted from either the start or the end of the array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceBound {
+    FromStart(usize),
+    FromEnd(usize),
+}
+
+impl SliceBound {
+    fn parse(bound: &str) -> Result<Option<Self>> {
+        let bound = bound.trim();
+        if bound.is_empty() {
+            return Ok(None);
+        }
+        let value: isize = bound
+            .parse()
+            .map_err(|_| eyre!("Invalid slice bound \"{bound}\" in key path"))?;
+        Ok(Some(if value < 0 {
+            Self::FromEnd(value.unsigned_abs())
+        } else {
+            Self::FromStart(value.unsigned_abs())
+        }))
+    }
+
+    fn resolve(&self, len: usize) -> usize {
+        match *self {
+            Self::FromStart(index) => index.min(len),
+            Self::FromEnd(index) => len.saturating_sub(index),
+        }
+    }
+}
+
+/// A Python style `[start:stop:step]` slice of the target array. Negative bounds count back from
+/// the end of the array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slice {
+    pub start: SliceBound,
+    pub stop: Option<SliceBound>,
+    pub step: usize,
+}
+
+impl Slice {
+    /// Parse the inside of the brackets of a slice, such as `100:200`, `-50:` or `::2`.
+    pub fn from_slice_str(slice_str: &str) -> Result<Self> {
+        let parts: Vec<&str> = slice_str.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(eyre!("Invalid slice \"{slice_str}\" in key path"));
+        }
+        let start = SliceBound::parse(parts[0])?.unwrap_or(SliceBound::FromStart(0));
+        let stop = SliceBound::parse(parts[1])?;
+        let step = match parts.get(2).map(|step| step.trim()) {
+            None | Some("") => 1,
+            Some(step) => match step.parse() {
+                Ok(0) | Err(_) => {
+                    return Err(eyre!(
+                        "Slice step must be a positive number, found \"{step}\" in key path"
+                    ))
+                }
+                Ok(step) => step,
+            },
+        };
+        Ok(Self { start, stop, step })
+    }
+
+    /// The range of indexes covered by the slice, for an array with `len` elements.
+    pub fn resolve(&self, len: usize) -> (usize, usize) {
+        let start = self.start.resolve(len);
+        let stop = self.stop.map_or(len, |stop| stop.resolve(len));
+        (start, stop)
+    }
+
+    /// Whether the element at `index` is selected from an array with `len` elements.
+    pub fn contains(&self, index: usize, len: usize) -> bool {
+        let (start, stop) = self.resolve(len);
+        (start..stop).contains(&index) && (index - start) % self.step == 0
+    }
+}
+
+/// What a `[...]` in a key path turned out to hold.
+enum Bracketed {
+    Segment(KeySegment),
+    Slice(Slice),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct KeyPath {
-    json_path: Vec<OwnedJsonEvent>,
+    json_path: Vec<KeySegment>,
+    slice: Option<Slice>,
 }
 
 #[allow(clippy::len_without_is_empty)]
 impl KeyPath {
+    /// Parse a key path such as `foo.bar`, `shards[2].rows`, `shards.2.rows` or `["a.b"].c`.
+    ///
+    /// Segments are separated by dots, and a backslash quotes the next character. A dotted segment
+    /// made up only of digits is an array index; quote one of the digits (`\2`) or use the bracket
+    /// form (`["2"]`) to mean an object key instead.
+    ///
+    /// A `*` segment matches every key of an object and a `[*]` segment every element of an array,
+    /// so the path can lead to many arrays. Quote the star (`\*` or `["*"]`) to mean a key named
+    /// `*`.
+    ///
+    /// Two dots lead to the next segment at any depth, so `..records` matches every `records` member
+    /// in the document, & `a..[0]` the first element of every array inside `a`.
+    ///
+    /// The path may end with a slice, such as `data.items[100:200]` or `[-50:]`, which selects the
+    /// elements of the target array to copy.
     pub fn from_kp_str(key_path_str: &str) -> Result<Self> {
         let mut json_path = Vec::new();
-        let mut quote = false;
+        let mut slice = None;
+        let mut chars = key_path_str.chars();
         let mut current_key = String::new();
-        for c in key_path_str.chars() {
-            if quote {
-                current_key.push(c);
-                quote = false;
-            } else {
+        let mut quoted = false;
+        let mut after_bracket = false;
+        while let Some(c) = chars.next() {
+            if slice.is_some() {
+                return Err(eyre!("A slice must come at the end of the key path"));
+            }
+            if after_bracket {
+                after_bracket = false;
                 match c {
-                    // TODO: add brackets
-                    '\\' => quote = true,
-                    '.' => {
-                        let element = OwnedJsonEvent::ObjectKey(std::mem::take(&mut current_key));
-                        json_path.push(element);
-                        current_key = String::new();
+                    '.' if chars.clone().next() != Some('.') => continue,
+                    '.' | '[' => {}
+                    _ => {
+                        return Err(eyre!(
+                            "Expecting '.' or '[' after ']' in key path, found '{c}'"
+                        ))
+                    }
+                }
+            }
+            match c {
+                '\\' => {
+                    if let Some(c) = chars.next() {
+                        current_key.push(c);
+                        quoted = true;
+                    }
+                }
+                '.' if chars.clone().next() == Some('.') => {
+                    let _ = chars.next();
+                    if !current_key.is_empty() || quoted {
+                        json_path.push(Self::dotted_segment(
+                            std::mem::take(&mut current_key),
+                            quoted,
+                        ));
+                        quoted = false;
+                    }
+                    json_path.push(KeySegment::AnyDescendants);
+                }
+                '.' => {
+                    json_path.push(Self::dotted_segment(
+                        std::mem::take(&mut current_key),
+                        quoted,
+                    ));
+                    quoted = false;
+                }
+                '[' => {
+                    if !current_key.is_empty() || quoted {
+                        json_path.push(Self::dotted_segment(
+                            std::mem::take(&mut current_key),
+                            quoted,
+                        ));
+                        quoted = false;
+                    }
+                    match Self::bracket_segment(&mut chars)? {
+                        Bracketed::Segment(segment) => json_path.push(segment),
+                        Bracketed::Slice(bracket_slice) => slice = Some(bracket_slice),
                     }
-                    _ => current_key.push(c),
+                    after_bracket = true;
                 }
+                _ => current_key.push(c),
             };
         }
         if !current_key.is_empty() {
-            let element = OwnedJsonEvent::ObjectKey(std::mem::take(&mut current_key));
-            json_path.push(element);
+            json_path.push(Self::dotted_segment(current_key, quoted));
+        }
+        if json_path.last() == Some(&KeySegment::AnyDescendants) {
+            return Err(eyre!("'..' must be followed by a key or index in key path"));
+        }
+        Ok(Self { json_path, slice })
+    }
+
+    fn dotted_segment(key: String, quoted: bool) -> KeySegment {
+        if !quoted && key == "*" {
+            return KeySegment::AnyKey;
+        }
+        if !quoted && !key.is_empty() && key.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(index) = key.parse() {
+                return KeySegment::ArrayIndex(index);
+            }
+        }
+        KeySegment::ObjectKey(key)
+    }
+
+    /// Parse the remainder of a `[...]` segment, after the opening bracket has been consumed. A key
+    /// in brackets may be quoted with either double or single quotes.
+    fn bracket_segment(chars: &mut Chars) -> Result<Bracketed> {
+        let mut contents = String::new();
+        if let Some(quote @ ('"' | '\'')) = chars.clone().next() {
+            let _ = chars.next();
+            loop {
+                match chars.next() {
+                    Some(c) if c == quote => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => contents.push(c),
+                        None => return Err(eyre!("Unterminated quoted key in key path")),
+                    },
+                    Some(c) => contents.push(c),
+                    None => return Err(eyre!("Unterminated quoted key in key path")),
+                }
+            }
+            return match chars.next() {
+                Some(']') => Ok(Bracketed::Segment(KeySegment::ObjectKey(contents))),
+                _ => Err(eyre!(
+                    "Expecting ']' after quoted key \"{contents}\" in key path"
+                )),
+            };
+        }
+        for c in chars.by_ref() {
+            if c == ']' {
+                return if contents.contains(':') {
+                    Slice::from_slice_str(&contents).map(Bracketed::Slice)
+                } else if contents.trim() == "*" {
+                    Ok(Bracketed::Segment(KeySegment::AnyIndex))
+                } else {
+                    contents
+                        .trim()
+                        .parse()
+                        .map(|index| Bracketed::Segment(KeySegment::ArrayIndex(index)))
+                        .map_err(|_| eyre!("Invalid array index \"{contents}\" in key path"))
+                };
+            }
+            contents.push(c);
+        }
+        Err(eyre!("Unterminated '[' in key path"))
+    }
+
+    /// Parse a JSONPath such as `$.store.book[0:10]`, `$['a.b'][*].c` or `$..records`.
+    ///
+    /// Only the parts of JSONPath that lead to a single kind of array are understood: the root `$`,
+    /// `.name` & `['name']` children, `[n]` indexes, `*` & `[*]` wildcards, `..` descent, and a
+    /// slice at the end. Unlike with `from_kp_str`, a dotted name is always an object key.
+    pub fn from_json_path(json_path_str: &str) -> Result<Self> {
+        let mut chars = json_path_str.trim().chars();
+        if chars.next() != Some('$') {
+            return Err(eyre!(
+                "A JSONPath must start with '$', found \"{json_path_str}\""
+            ));
+        }
+        let mut json_path = Vec::new();
+        let mut slice = None;
+        while let Some(c) = chars.next() {
+            if slice.is_some() {
+                return Err(eyre!("A slice must come at the end of the JSONPath"));
+            }
+            match c {
+                '.' => {
+                    if chars.clone().next() == Some('.') {
+                        let _ = chars.next();
+                        json_path.push(KeySegment::AnyDescendants);
+                        if chars.clone().next() == Some('[') {
+                            continue;
+                        }
+                    }
+                    let mut name = String::new();
+                    while let Some(c) = chars.clone().next() {
+                        if c == '.' || c == '[' {
+                            break;
+                        }
+                        name.push(c);
+                        let _ = chars.next();
+                    }
+                    json_path.push(match name.as_str() {
+                        "" => return Err(eyre!("Expecting a name after '.' in JSONPath")),
+                        "*" => KeySegment::AnyKey,
+                        _ => KeySegment::ObjectKey(name),
+                    });
+                }
+                '[' => match Self::bracket_segment(&mut chars)? {
+                    Bracketed::Segment(segment) => json_path.push(segment),
+                    Bracketed::Slice(bracket_slice) => slice = Some(bracket_slice),
+                },
+                _ => return Err(eyre!("Expecting '.' or '[' in JSONPath, found '{c}'")),
+            }
+        }
+        if slice.is_some() && json_path.last() == Some(&KeySegment::AnyDescendants) {
+            return Err(eyre!(
+                "'..' must be followed by a name or index in JSONPath"
+            ));
+        }
+        Ok(Self { json_path, slice })
+    }
+
+    /// Parse an RFC 6901 JSON Pointer such as `/data/items`, in which `~1` stands for `/` & `~0` for
+    /// `~`. As in `from_kp_str`, a segment that is a number (without leading zeros) is an array
+    /// index.
+    pub fn from_pointer(pointer: &str) -> Result<Self> {
+        if pointer.is_empty() {
+            return Ok(Self::default());
+        }
+        let Some(tokens) = pointer.strip_prefix('/') else {
+            return Err(eyre!(
+                "A JSON Pointer must start with '/', found \"{pointer}\""
+            ));
+        };
+        let mut json_path = Vec::new();
+        for token in tokens.split('/') {
+            let mut key = String::new();
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '~' => match chars.next() {
+                        Some('0') => key.push('~'),
+                        Some('1') => key.push('/'),
+                        _ => {
+                            return Err(eyre!(
+                                "'~' must be followed by '0' or '1' in JSON Pointer \"{pointer}\""
+                            ))
+                        }
+                    },
+                    _ => key.push(c),
+                }
+            }
+            let is_index = !key.is_empty()
+                && key.bytes().all(|b| b.is_ascii_digit())
+                && (key == "0" || !key.starts_with('0'));
+            json_path.push(match key.parse() {
+                Ok(index) if is_index => KeySegment::ArrayIndex(index),
+                _ => KeySegment::ObjectKey(key),
+            });
+        }
+        Ok(Self {
+            json_path,
+            slice: None,
+        })
+    }
+
+    /// The key path written as a JSON Pointer, for display. Pointers have no wildcards, so `*` & `[*]`
+    /// are shown as `*` & `..` as `**`, and any slice is left out.
+    pub fn to_pointer(&self) -> String {
+        let mut pointer = String::new();
+        for segment in &self.json_path {
+            pointer.push('/');
+            match segment {
+                KeySegment::ObjectKey(key) => {
+                    pointer.push_str(&key.replace('~', "~0").replace('/', "~1"))
+                }
+                KeySegment::ArrayIndex(index) => pointer.push_str(&index.to_string()),
+                KeySegment::AnyKey | KeySegment::AnyIndex => pointer.push('*'),
+                KeySegment::AnyDescendants => pointer.push_str("**"),
+            }
         }
-        Ok(Self { json_path })
+        pointer
     }
 
-    pub fn iterator(&self) -> impl Iterator<Item = &OwnedJsonEvent> + '_ {
+    /// The slice at the end of the key path, if there is one.
+    pub fn slice(&self) -> Option<Slice> {
+        self.slice
+    }
+
+    pub fn iterator(&self) -> impl Iterator<Item = &KeySegment> + '_ {
         self.json_path.iter()
     }
 
+    pub fn get(&self, index: usize) -> Option<&KeySegment> {
+        self.json_path.get(index)
+    }
+
     pub fn len(&self) -> usize {
         self.json_path.len()
     }
+
+    /// Whether the key path can lead to more than one array.
+    pub fn has_wildcards(&self) -> bool {
+        self.json_path.iter().any(KeySegment::is_wildcard)
+    }
+}
+
+impl From<Vec<KeySegment>> for KeyPath {
+    fn from(json_path: Vec<KeySegment>) -> Self {
+        Self {
+            json_path,
+            slice: None,
+        }
+    }
 }
 
 impl Index<usize> for KeyPath {
-    type Output = OwnedJsonEvent;
+    type Output = KeySegment;
     fn index(&self, idx: usize) -> &Self::Output {
         &self.json_path[idx]
     }
@@ -87,8 +465,8 @@ impl Index<usize> for KeyPath {
 
 #[cfg(test)]
 mod tests {
-    use crate::key_path::OwnedJsonEvent::ObjectKey;
-    use crate::key_path::{KeyPath, OwnedJsonEvent};
+    use crate::key_path::KeySegment::{AnyDescendants, AnyIndex, AnyKey, ArrayIndex, ObjectKey};
+    use crate::key_path::{KeyPath, KeySegment, OwnedJsonEvent, Slice, SliceBound};
     use json_event_parser::JsonEvent;
 
     #[test]
@@ -121,6 +499,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_owned_json_event_round_trip() {
+        let events = [
+            JsonEvent::ObjectKey("key"),
+            JsonEvent::String("string"),
+            JsonEvent::Number("27"),
+            JsonEvent::Boolean(false),
+            JsonEvent::Null,
+            JsonEvent::StartArray,
+            JsonEvent::EndArray,
+            JsonEvent::StartObject,
+            JsonEvent::EndObject,
+            JsonEvent::Eof,
+        ];
+        for event in events {
+            assert_eq!(event, OwnedJsonEvent::from(event).as_json_event());
+        }
+    }
+
     #[test]
     fn test_blank_key_path() {
         let key_path = KeyPath::from_kp_str("").unwrap();
@@ -131,7 +528,7 @@ mod tests {
     fn test_one_element_with_backslash_dot() {
         let key_path = KeyPath::from_kp_str("foo\\.bar").unwrap();
         assert_eq!(1, key_path.len());
-        let event_0 = OwnedJsonEvent::ObjectKey("foo.bar".to_string());
+        let event_0 = KeySegment::ObjectKey("foo.bar".to_string());
         assert_eq!(event_0, key_path[0]);
     }
 
@@ -139,7 +536,7 @@ mod tests {
     fn test_one_element() {
         let key_path = KeyPath::from_kp_str("foo").unwrap();
         assert_eq!(1, key_path.len());
-        let event_0 = OwnedJsonEvent::ObjectKey("foo".to_string());
+        let event_0 = KeySegment::ObjectKey("foo".to_string());
         assert_eq!(event_0, key_path[0]);
     }
 
@@ -170,4 +567,216 @@ mod tests {
             assert_eq!(events[index], key_path[index]);
         }
     }
+
+    #[test]
+    fn test_bracket_index() {
+        let key_path = KeyPath::from_kp_str("shards[2].rows").unwrap();
+        assert_eq!(3, key_path.len());
+        assert_eq!(ObjectKey("shards".to_string()), key_path[0]);
+        assert_eq!(ArrayIndex(2), key_path[1]);
+        assert_eq!(ObjectKey("rows".to_string()), key_path[2]);
+    }
+
+    #[test]
+    fn test_dotted_index() {
+        let key_path = KeyPath::from_kp_str("shards.2.rows").unwrap();
+        assert_eq!(3, key_path.len());
+        assert_eq!(ArrayIndex(2), key_path[1]);
+        let key_path = KeyPath::from_kp_str("shards.\\2.rows").unwrap();
+        assert_eq!(ObjectKey("2".to_string()), key_path[1]);
+    }
+
+    #[test]
+    fn test_leading_and_consecutive_brackets() {
+        let key_path = KeyPath::from_kp_str("[0][1]").unwrap();
+        assert_eq!(2, key_path.len());
+        assert_eq!(ArrayIndex(0), key_path[0]);
+        assert_eq!(ArrayIndex(1), key_path[1]);
+    }
+
+    #[test]
+    fn test_quoted_bracket_key() {
+        let key_path = KeyPath::from_kp_str("[\"a.b\"][\"2\"].[\"say \\\"hi\\\"\"]").unwrap();
+        assert_eq!(3, key_path.len());
+        assert_eq!(ObjectKey("a.b".to_string()), key_path[0]);
+        assert_eq!(ObjectKey("2".to_string()), key_path[1]);
+        assert_eq!(ObjectKey("say \"hi\"".to_string()), key_path[2]);
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let key_path = KeyPath::from_kp_str("regions.*.items[*].tags").unwrap();
+        assert_eq!(5, key_path.len());
+        assert_eq!(AnyKey, key_path[1]);
+        assert_eq!(AnyIndex, key_path[3]);
+        assert!(key_path.has_wildcards());
+        let key_path = KeyPath::from_kp_str("a.\\*[\"*\"]").unwrap();
+        assert_eq!(ObjectKey("*".to_string()), key_path[1]);
+        assert_eq!(ObjectKey("*".to_string()), key_path[2]);
+        assert!(!key_path.has_wildcards());
+    }
+
+    #[test]
+    fn test_descendants() {
+        let key_path = KeyPath::from_kp_str("..records").unwrap();
+        assert_eq!(2, key_path.len());
+        assert_eq!(AnyDescendants, key_path[0]);
+        assert_eq!(ObjectKey("records".to_string()), key_path[1]);
+        let key_path = KeyPath::from_kp_str("a..b[0]..[*]").unwrap();
+        let segments = [
+            ObjectKey("a".to_string()),
+            AnyDescendants,
+            ObjectKey("b".to_string()),
+            ArrayIndex(0),
+            AnyDescendants,
+            AnyIndex,
+        ];
+        assert_eq!(segments.len(), key_path.len());
+        for (index, segment) in segments.iter().enumerate() {
+            assert_eq!(segment, &key_path[index]);
+        }
+        assert!(key_path.has_wildcards());
+        assert!(KeyPath::from_kp_str("a..").is_err());
+        assert!(KeyPath::from_kp_str("a..[1:]").is_err());
+    }
+
+    #[test]
+    fn test_single_quoted_bracket_key() {
+        let key_path = KeyPath::from_kp_str("['a.b']['it\\'s']").unwrap();
+        assert_eq!(ObjectKey("a.b".to_string()), key_path[0]);
+        assert_eq!(ObjectKey("it's".to_string()), key_path[1]);
+    }
+
+    #[test]
+    fn test_json_path() {
+        let key_path = KeyPath::from_json_path("$.store.book[0:10]").unwrap();
+        assert_eq!(2, key_path.len());
+        assert_eq!(ObjectKey("store".to_string()), key_path[0]);
+        assert_eq!(ObjectKey("book".to_string()), key_path[1]);
+        assert_eq!(
+            Some(Slice::from_slice_str("0:10").unwrap()),
+            key_path.slice()
+        );
+        let key_path = KeyPath::from_json_path("$['a.b'][\"c\"].0[2][*].*..d..[1]").unwrap();
+        let segments = [
+            ObjectKey("a.b".to_string()),
+            ObjectKey("c".to_string()),
+            ObjectKey("0".to_string()),
+            ArrayIndex(2),
+            AnyIndex,
+            AnyKey,
+            AnyDescendants,
+            ObjectKey("d".to_string()),
+            AnyDescendants,
+            ArrayIndex(1),
+        ];
+        assert_eq!(segments.len(), key_path.len());
+        for (index, segment) in segments.iter().enumerate() {
+            assert_eq!(segment, &key_path[index]);
+        }
+        assert_eq!(0, KeyPath::from_json_path("$").unwrap().len());
+    }
+
+    #[test]
+    fn test_bad_json_paths() {
+        assert!(KeyPath::from_json_path("store.book").is_err());
+        assert!(KeyPath::from_json_path("$.").is_err());
+        assert!(KeyPath::from_json_path("$..").is_err());
+        assert!(KeyPath::from_json_path("$.a[1:2].b").is_err());
+        assert!(KeyPath::from_json_path("$.a[-1]").is_err());
+        assert!(KeyPath::from_json_path("$a").is_err());
+        assert!(KeyPath::from_json_path("$..[:2]").is_err());
+    }
+
+    #[test]
+    fn test_pointer() {
+        let key_path = KeyPath::from_pointer("/data/items").unwrap();
+        assert_eq!(2, key_path.len());
+        assert_eq!(ObjectKey("data".to_string()), key_path[0]);
+        assert_eq!(ObjectKey("items".to_string()), key_path[1]);
+        let key_path = KeyPath::from_pointer("/a~1b/~01/2/0/02//-").unwrap();
+        let segments = [
+            ObjectKey("a/b".to_string()),
+            ObjectKey("~1".to_string()),
+            ArrayIndex(2),
+            ArrayIndex(0),
+            ObjectKey("02".to_string()),
+            ObjectKey("".to_string()),
+            ObjectKey("-".to_string()),
+        ];
+        assert_eq!(segments.len(), key_path.len());
+        for (index, segment) in segments.iter().enumerate() {
+            assert_eq!(segment, &key_path[index]);
+        }
+        assert_eq!("/a~1b/~01/2/0/02//-", key_path.to_pointer());
+        assert_eq!(0, KeyPath::from_pointer("").unwrap().len());
+        assert!(KeyPath::from_pointer("data").is_err());
+        assert!(KeyPath::from_pointer("/a~2").is_err());
+        assert!(KeyPath::from_pointer("/a~").is_err());
+    }
+
+    #[test]
+    fn test_to_pointer() {
+        assert_eq!("", KeyPath::default().to_pointer());
+        let key_path = KeyPath::from_kp_str("a.b~c[3]..d.*[*][1:]").unwrap();
+        assert_eq!("/a/b~0c/3/**/d/*/*", key_path.to_pointer());
+    }
+
+    #[test]
+    fn test_bad_brackets() {
+        assert!(KeyPath::from_kp_str("foo[").is_err());
+        assert!(KeyPath::from_kp_str("foo[x]").is_err());
+        assert!(KeyPath::from_kp_str("foo[\"x]").is_err());
+        assert!(KeyPath::from_kp_str("foo[1]bar").is_err());
+    }
+
+    #[test]
+    fn test_slice() {
+        let key_path = KeyPath::from_kp_str("data.items[100:200]").unwrap();
+        assert_eq!(2, key_path.len());
+        let slice = Slice {
+            start: SliceBound::FromStart(100),
+            stop: Some(SliceBound::FromStart(200)),
+            step: 1,
+        };
+        assert_eq!(Some(slice), key_path.slice());
+        let key_path = KeyPath::from_kp_str("[-50:]").unwrap();
+        assert_eq!(0, key_path.len());
+        let slice = Slice {
+            start: SliceBound::FromEnd(50),
+            stop: None,
+            step: 1,
+        };
+        assert_eq!(Some(slice), key_path.slice());
+        let key_path = KeyPath::from_kp_str("a[ : -2 : 3 ]").unwrap();
+        let slice = Slice {
+            start: SliceBound::FromStart(0),
+            stop: Some(SliceBound::FromEnd(2)),
+            step: 3,
+        };
+        assert_eq!(Some(slice), key_path.slice());
+        assert_eq!(None, KeyPath::from_kp_str("a[1]").unwrap().slice());
+    }
+
+    #[test]
+    fn test_bad_slices() {
+        assert!(KeyPath::from_kp_str("a[1:2].b").is_err());
+        assert!(KeyPath::from_kp_str("a[1:2][0]").is_err());
+        assert!(KeyPath::from_kp_str("a[1:2:0]").is_err());
+        assert!(KeyPath::from_kp_str("a[1:2:-1]").is_err());
+        assert!(KeyPath::from_kp_str("a[1:2:3:4]").is_err());
+        assert!(KeyPath::from_kp_str("a[x:]").is_err());
+        assert!(KeyPath::from_kp_str("a[]").is_err());
+    }
+
+    #[test]
+    fn test_slice_contains() {
+        let slice = Slice::from_slice_str("-4:-1:2").unwrap();
+        assert_eq!((6, 9), slice.resolve(10));
+        let selected: Vec<usize> = (0..10).filter(|i| slice.contains(*i, 10)).collect();
+        assert_eq!(vec![6, 8], selected);
+        assert_eq!((0, 2), slice.resolve(3));
+        let slice = Slice::from_slice_str("5:").unwrap();
+        assert_eq!((3, 3), slice.resolve(3));
+    }
 }
@@ -1,3 +1,9 @@
+pub mod array_scan;
 pub mod copy_loop;
 pub mod copy_selector;
+pub mod decompress;
+pub mod json_input;
+pub mod json_output;
 pub mod key_path;
+pub mod predicate;
+pub mod projection;
@@ -1,11 +1,17 @@
 extern crate headj;
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 use env_logger::builder;
-use eyre::Result;
-use headj::copy_loop::copy_loop;
-use headj::copy_selector::CopySelector;
+use eyre::{eyre, Result};
+use headj::array_scan::{find_auto_target, scan_loop, ScanFormat};
+use headj::copy_loop::{copy_loop, count_loop, ArrayStats};
+use headj::copy_selector::{Annotation, ContextPolicy, CopySelector};
+use headj::decompress::decompress;
+use headj::json_input::InputFormat;
+use headj::json_output::{Indent, OutputFormat, OutputKind, Separator};
 use headj::key_path::KeyPath;
-use log::{error, LevelFilter};
+use headj::predicate::Predicate;
+use headj::projection::Projection;
+use log::{debug, error, info, LevelFilter};
 use std::fs::File;
 #[allow(unused_imports)]
 use std::io::{self, BufRead, Read, Write};
@@ -13,6 +19,8 @@ use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(group(ArgGroup::new("sampling").args(&["sample", "sample-rate"])))]
+#[clap(group(ArgGroup::new("depth-limited").args(&["all-arrays", "scan"])))]
 struct Args {
     ///The JSON file to read from. If none is specified, reads from Standard Input
     #[clap(value_parser)]
@@ -21,46 +29,365 @@ struct Args {
     #[clap(short, long, value_parser)]
     out_file: Option<PathBuf>,
     /// The JSON key of the array to copy from. If none specified, treat the input JSON as an array.
+    /// A slice at the end, such as 'items[10:20]' or 'items[-5:]', replaces --skip, --count & --step.
+    /// '*' & '[*]' match any key or array index, & '..' any number of levels. May be given more
+    /// than once, to copy from several arrays in one pass
     #[clap(short, long, value_parser)]
-    key: Option<String>,
+    key: Vec<String>,
+    /// A JSONPath to the array to copy from, such as '$.store.book[0:10]', as an alternative to
+    /// --key. May be given more than once
+    #[clap(long, value_parser)]
+    path: Vec<String>,
+    /// A JSON Pointer (RFC 6901) to the array to copy from, such as '/data/items', as an alternative
+    /// to --key. May be given more than once
+    #[clap(long, value_parser)]
+    pointer: Vec<String>,
+    /// Read the input as newline delimited JSON (JSON Lines), with each line an element of the array
+    /// to copy from. Lines that aren't valid JSON are left out with a warning
+    #[clap(long, action, conflicts_with_all = &["auto", "scan"])]
+    ndjson: bool,
+    /// Read the input as an RFC 7464 JSON text sequence, with each record an element of the array to
+    /// copy from, or with --multi-document a document of its own. Records that are cut short or
+    /// aren't valid JSON are left out with a warning
+    #[clap(long, action, conflicts_with_all = &["ndjson", "auto", "scan"])]
+    json_seq: bool,
+    /// Read a stream of JSON documents one after another, such as '{...}{...}', copying from each of
+    /// them in turn. Each output document goes on its own line
+    #[clap(long, action, conflicts_with_all = &["ndjson", "auto", "scan"])]
+    multi_document: bool,
+    /// With --multi-document, carry the selection on from one document to the next, as if their
+    /// arrays were all one array, instead of starting it over for each document
+    #[clap(long, action, conflicts_with_all = &["ndjson", "auto", "scan"])]
+    across_documents: bool,
+    /// With --multi-document, stop after the first DOCUMENTS documents
+    #[clap(long, value_parser, conflicts_with_all = &["ndjson", "auto", "scan"])]
+    documents: Option<usize>,
     /// Nicely format the output JSON with indentation & newlines.
     #[clap(short, long, action)]
     format_output: bool,
+    /// Write the output JSON without any whitespace (the default)
+    #[clap(long, action, conflicts_with = "format-output")]
+    compact: bool,
+    /// The indentation used by --format-output: either a number of spaces or "tab"
+    #[clap(long, value_parser, default_value = "2")]
+    indent: Indent,
+    /// Write "json", "ndjson" to write each copied element on a line of its own, with nothing around
+    /// them, or "json-seq" to write each element as a record of an RFC 7464 JSON text sequence. With
+    /// --multi-document, the elements of all the documents run on with nothing between documents
+    #[clap(
+        long,
+        value_parser,
+        default_value = "json",
+        conflicts_with = "format-output"
+    )]
+    output_format: OutputKind,
+    /// With --output-format ndjson, what to write after each element instead of a newline, such as
+    /// '\0'
+    #[clap(long, value_parser, default_value = "\\n")]
+    separator: Separator,
     /// Don't print any status, diagnostic or error messages
     #[clap(short, long, action)]
     quiet: bool,
     /// Output _only_ the target JSON array
     #[clap(short, long, action)]
     no_context: bool,
+    /// How much of the document around the target array is copied: "full" (the default),
+    /// "path-only" (only the arrays & objects that lead to the target) or "summarize" (other arrays
+    /// & long strings are cut short). Not with --output-format ndjson or json-seq
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &["no-context", "all-arrays", "count-only"]
+    )]
+    context: Option<ContextPolicy>,
+    /// With --context summarize, the number of elements kept of each other array (default: 3)
+    #[clap(long, value_parser)]
+    context_elements: Option<usize>,
+    /// With --context summarize, the number of characters kept of each string (default: 80)
+    #[clap(long, value_parser)]
+    context_string_length: Option<usize>,
+    /// Mark where elements were left out of an array with an element such as
+    /// {"$headj_elided": 99900}, in each gap between the copied elements & at the start & end
+    #[clap(long, action, conflicts_with = "count-only")]
+    annotate: bool,
+    /// With --annotate, mark left out elements with this string instead, with each '{}' replaced by
+    /// the number of elements
+    #[clap(long, value_parser, value_name = "TEMPLATE", requires = "annotate")]
+    annotate_string: Option<String>,
     /// Number of elements to skip before copying (default: 0)
-    #[clap(short, long, value_parser, default_value_t = 0)]
-    skip: usize,
+    #[clap(short, long, value_parser)]
+    skip: Option<usize>,
     /// Number of elements to copy to the output (default: 100)
-    #[clap(short, long, value_parser, default_value_t = 100)]
-    count: usize,
+    #[clap(short, long, value_parser)]
+    count: Option<usize>,
+    /// Copy from the root array, or else the longest array that is a member of the root, or else the
+    /// first array in the document. Reads INPUT_FILE twice
+    #[clap(
+        long,
+        action,
+        requires = "input-file",
+        conflicts_with_all = &["key", "path", "pointer", "all-arrays"]
+    )]
+    auto: bool,
+    /// Cut every array in the document, at any depth, down to ALL_ARRAYS elements
+    #[clap(long, value_parser, conflicts_with_all = &["key", "path", "pointer", "count"])]
+    all_arrays: Option<usize>,
+    /// With --all-arrays, copy arrays nested inside more than MAX_DEPTH arrays & objects in full.
+    /// With --scan, leave them out of the list
+    #[clap(long, value_parser, requires = "depth-limited")]
+    max_depth: Option<usize>,
+    /// Instead of copying, list every array in the input with its pointer, nesting depth, length &
+    /// the byte offsets of its brackets. Each array is listed as soon as it ends, so nested arrays
+    /// come before the arrays that contain them
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = &["key", "path", "pointer", "auto", "all-arrays"]
+    )]
+    scan: bool,
+    /// How --scan lists the arrays: "table" or "json"
+    #[clap(long, value_parser, default_value = "table")]
+    scan_format: ScanFormat,
+    /// Instead of copying, print the number of elements in the target array
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = &["count", "tail", "sample", "sample-rate", "all-arrays", "scan"]
+    )]
+    count_only: bool,
+    /// With --count-only, also print the smallest, largest & average size of the elements in bytes,
+    /// when written as compact JSON
+    #[clap(long, action, requires = "count-only")]
+    sizes: bool,
+    /// Only copy elements that match PREDICATE, such as 'status == "failed"' or '.amount > 1000'.
+    /// Skipping, counting & the other selection options only see the matching elements
+    #[clap(long = "where", value_parser, value_name = "PREDICATE", conflicts_with_all = &["all-arrays", "scan"])]
+    predicate: Option<Predicate>,
+    /// Keep only these fields of each copied element, such as 'id,name,user.email'. Fields are key
+    /// paths inside the element, & may use wildcards
+    #[clap(
+        long,
+        value_parser,
+        value_delimiter = ',',
+        conflicts_with_all = &["all-arrays", "scan"]
+    )]
+    fields: Vec<String>,
+    /// Leave these fields out of each copied element, keeping all of the others
+    #[clap(
+        long,
+        value_parser,
+        value_delimiter = ',',
+        conflicts_with_all = &["fields", "all-arrays", "scan"]
+    )]
+    exclude_fields: Vec<String>,
+    /// Copy only every STEP-th element, starting with the first one after the skipped elements
+    #[clap(long, value_parser, default_value_t = 1)]
+    step: usize,
+    /// Copy the last TAIL elements of the array, instead of skipping & counting from the start
+    #[clap(short, long, value_parser, conflicts_with_all = &["skip", "count", "step"])]
+    tail: Option<usize>,
+    /// Copy a random sample of SAMPLE elements of the array, in their original order
+    #[clap(long, value_parser, conflicts_with_all = &["count", "tail", "step"])]
+    sample: Option<usize>,
+    /// Copy each element of the array with a probability of SAMPLE_RATE (between 0 and 1)
+    #[clap(long, value_parser, conflicts_with_all = &["count", "tail", "sample", "step"])]
+    sample_rate: Option<f64>,
+    /// Seed for --sample & --sample-rate, so that the same elements are chosen every time
+    #[clap(long, value_parser, requires = "sampling")]
+    seed: Option<u64>,
     /// Activate extra debugging output
     #[clap(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 }
 
-fn perform_copy(args: Args) -> Result<()> {
-    let key_path = if let Some(key_str) = args.key {
-        KeyPath::from_kp_str(&key_str)?
+/// Open the input, decompressing it if it is gzip, zstd, bzip2 or xz compressed.
+fn open_input(args: &Args) -> Result<Box<dyn BufRead>> {
+    if let Some(in_file) = &args.input_file {
+        let reader = Box::new(io::BufReader::new(File::open(in_file)?));
+        decompress(reader, Some(in_file.as_path()))
     } else {
-        KeyPath::default()
-    };
-    let mut out_writer: Box<dyn Write> = if let Some(out_file) = args.out_file {
+        decompress(Box::new(io::stdin().lock()), None)
+    }
+}
+
+fn open_output(args: &Args) -> Result<Box<dyn Write>> {
+    Ok(if let Some(out_file) = &args.out_file {
         Box::new(File::create(out_file)?)
     } else {
         Box::new(io::stdout())
-    };
-    let in_reader: Box<dyn BufRead> = if let Some(in_file) = args.input_file {
-        Box::new(io::BufReader::new(File::open(in_file)?))
+    })
+}
+
+fn input_format(args: &Args) -> InputFormat {
+    let documents = args.multi_document || args.across_documents || args.documents.is_some();
+    if args.ndjson {
+        InputFormat::Ndjson
+    } else if args.json_seq && documents {
+        InputFormat::JsonSeqDocuments(args.documents)
+    } else if args.json_seq {
+        InputFormat::JsonSeq
+    } else if documents {
+        InputFormat::Documents(args.documents)
+    } else {
+        InputFormat::Json
+    }
+}
+
+fn output_format(args: &Args) -> OutputFormat {
+    if args.output_format == OutputKind::Ndjson {
+        OutputFormat::Lines(args.separator.clone())
+    } else if args.output_format == OutputKind::JsonSeq {
+        OutputFormat::Sequence
+    } else if args.format_output {
+        OutputFormat::Pretty(args.indent)
+    } else {
+        OutputFormat::Compact
+    }
+}
+
+fn perform_scan(args: Args) -> Result<()> {
+    let mut out_writer = open_output(&args)?;
+    let in_reader = open_input(&args)?;
+    scan_loop(
+        in_reader,
+        &mut out_writer,
+        args.max_depth,
+        args.scan_format,
+        output_format(&args),
+    )
+}
+
+fn perform_copy(args: Args) -> Result<()> {
+    let mut key_paths = args
+        .key
+        .iter()
+        .map(|key_str| KeyPath::from_kp_str(key_str))
+        .chain(
+            args.path
+                .iter()
+                .map(|path_str| KeyPath::from_json_path(path_str)),
+        )
+        .chain(
+            args.pointer
+                .iter()
+                .map(|pointer| KeyPath::from_pointer(pointer)),
+        )
+        .collect::<Result<Vec<_>>>()?
+        .into_iter();
+    let mut key_path = key_paths.next().unwrap_or_default();
+    if args.auto {
+        // --auto requires an input file, so it can be read twice
+        key_path = find_auto_target(open_input(&args)?)?;
+        info!("Copying from the array at \"{}\"", key_path.to_pointer());
+    }
+    let mut out_writer = open_output(&args)?;
+    let in_reader = open_input(&args)?;
+    let input = input_format(&args);
+    let format = output_format(&args);
+    let count = if args.count_only {
+        usize::MAX
     } else {
-        Box::new(io::stdin().lock())
+        args.all_arrays.or(args.count).unwrap_or(100)
     };
-    let mut copy_selector = CopySelector::new(key_path, args.count, args.skip, args.no_context);
-    copy_loop(in_reader, &mut out_writer, &mut copy_selector)?;
+    let lines = args.output_format != OutputKind::Json;
+    if lines
+        && (args.context.is_some()
+            || args.context_elements.is_some()
+            || args.context_string_length.is_some())
+    {
+        return Err(eyre!(
+            "--output-format ndjson & json-seq only write the copied elements, so they cannot be combined with --context, --context-elements or --context-string-length"
+        ));
+    }
+    let no_context = args.no_context || args.count_only || lines;
+    let mut copy_selector = CopySelector::new(key_path, count, args.skip.unwrap_or(0), no_context);
+    for key_path in key_paths {
+        copy_selector = copy_selector.with_target(key_path);
+    }
+    // A slice in a key takes the place of these, so they can't be given when every key has one
+    if args.skip.is_some() {
+        copy_selector.check_not_all_sliced("--skip")?;
+    }
+    if args.count.is_some() {
+        copy_selector.check_not_all_sliced("--count")?;
+    }
+    copy_selector = copy_selector.with_step(args.step)?;
+    if args.all_arrays.is_some() {
+        copy_selector = copy_selector.with_all_arrays(args.max_depth);
+    }
+    let mut context = args.context.unwrap_or_default();
+    if let ContextPolicy::Summarize {
+        elements,
+        string_len,
+    } = &mut context
+    {
+        *elements = args.context_elements.unwrap_or(*elements);
+        *string_len = args.context_string_length.unwrap_or(*string_len);
+    }
+    copy_selector = copy_selector.with_context(context);
+    if args.annotate {
+        let annotation = match args.annotate_string {
+            Some(template) => Annotation::String(template),
+            None => Annotation::Object,
+        };
+        copy_selector = copy_selector.with_annotation(annotation);
+    }
+    if let Some(predicate) = args.predicate {
+        copy_selector = copy_selector.with_predicate(predicate);
+    }
+    if !args.fields.is_empty() || !args.exclude_fields.is_empty() {
+        let fields = args
+            .fields
+            .iter()
+            .chain(&args.exclude_fields)
+            .map(|field| KeyPath::from_kp_str(field))
+            .collect::<Result<Vec<_>>>()?;
+        let projection = if args.fields.is_empty() {
+            Projection::exclude(fields)?
+        } else {
+            Projection::include(fields)?
+        };
+        copy_selector = copy_selector.with_projection(projection);
+    }
+    if let Some(tail) = args.tail {
+        copy_selector = copy_selector.with_tail(tail)?;
+    }
+    if args.sample.is_some() || args.sample_rate.is_some() {
+        let seed = args.seed.unwrap_or_else(rand::random);
+        debug!("Sampling with seed {seed}");
+        if let Some(sample) = args.sample {
+            copy_selector = copy_selector.with_sample(sample, seed)?;
+        } else if let Some(sample_rate) = args.sample_rate {
+            copy_selector = copy_selector.with_sample_rate(sample_rate, seed)?;
+        }
+    }
+    if args.across_documents {
+        copy_selector = copy_selector.with_windows_across_documents()?;
+    }
+    if args.count_only {
+        let stats = count_loop(in_reader, &mut copy_selector, input)?;
+        write_stats(&mut out_writer, &stats, args.sizes)?;
+    } else {
+        copy_loop(
+            in_reader,
+            &mut out_writer,
+            &mut copy_selector,
+            input,
+            format,
+        )?;
+    }
+    Ok(())
+}
+
+fn write_stats(out_writer: &mut impl Write, stats: &ArrayStats, sizes: bool) -> Result<()> {
+    if sizes {
+        writeln!(out_writer, "count: {}", stats.count)?;
+        writeln!(out_writer, "min_bytes: {}", stats.min_size)?;
+        writeln!(out_writer, "max_bytes: {}", stats.max_size)?;
+        writeln!(out_writer, "avg_bytes: {:.2}", stats.avg_size())?;
+    } else {
+        writeln!(out_writer, "{}", stats.count)?;
+    }
     Ok(())
 }
 
@@ -84,10 +411,15 @@ fn main() {
     let quiet = args.quiet;
     let debug = args.debug;
     setup_logging(quiet, debug, None);
-    match perform_copy(args) {
+    let result = if args.scan {
+        perform_scan(args)
+    } else {
+        perform_copy(args)
+    };
+    match result {
         Ok(_) => {}
         Err(e) => {
-            error!("Error: {e}");
+            error!("Error: {e:#}");
         }
     }
 }
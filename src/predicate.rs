@@ -0,0 +1,454 @@
+use crate::key_path::{KeyPath, KeySegment, OwnedJsonEvent};
+use eyre::{eyre, Report, Result};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// A constant in a predicate.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+/// Either side of a comparison: a path into the element, or a constant.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Path(KeyPath),
+    Literal(Literal),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A condition that an element of the target array must meet to be selected, such as
+/// `status == "failed"` or `.amount > 1000 && !.deleted`.
+///
+/// Paths are written in the `--key` syntax, optionally starting with a dot, and `.` on its own is
+/// the element itself. A path that leads nowhere is `null`. A path on its own is true unless it is
+/// `null` or `false`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Predicate {
+    Compare(Operand, Comparison, Operand),
+    Truthy(Operand),
+    Not(Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Operand(Operand),
+    Compare(Comparison),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// Characters that end a path or keyword.
+fn ends_word(c: char) -> bool {
+    c.is_whitespace() || "()=!<>&|".contains(c)
+}
+
+fn tokenize(predicate_str: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = predicate_str.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            let _ = chars.next();
+            continue;
+        }
+        let _ = chars.next();
+        let next = chars.peek().copied();
+        let token = match (c, next) {
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            ('&', Some('&')) | ('|', Some('|')) | ('=', Some('=')) | ('!', Some('=')) => {
+                let _ = chars.next();
+                match c {
+                    '&' => Token::And,
+                    '|' => Token::Or,
+                    '=' => Token::Compare(Comparison::Eq),
+                    _ => Token::Compare(Comparison::Ne),
+                }
+            }
+            ('<' | '>', Some('=')) => {
+                let _ = chars.next();
+                Token::Compare(if c == '<' {
+                    Comparison::Le
+                } else {
+                    Comparison::Ge
+                })
+            }
+            ('<', _) => Token::Compare(Comparison::Lt),
+            ('>', _) => Token::Compare(Comparison::Gt),
+            ('!', _) => Token::Not,
+            ('"', _) => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some('r') => string.push('\r'),
+                            Some(c) => string.push(c),
+                            None => return Err(eyre!("Unterminated string in predicate")),
+                        },
+                        Some(c) => string.push(c),
+                        None => return Err(eyre!("Unterminated string in predicate")),
+                    }
+                }
+                Token::Operand(Operand::Literal(Literal::String(string)))
+            }
+            _ if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let mut number = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                        break;
+                    }
+                    number.push(c);
+                    let _ = chars.next();
+                }
+                let value = number
+                    .parse()
+                    .map_err(|_| eyre!("Invalid number \"{number}\" in predicate"))?;
+                Token::Operand(Operand::Literal(Literal::Number(value)))
+            }
+            ('&' | '|' | '=', _) => {
+                return Err(eyre!("Unexpected '{c}' in predicate, expecting '{c}{c}'"))
+            }
+            _ => {
+                let mut word = String::from(c);
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if !quoted && ends_word(c) {
+                        break;
+                    }
+                    if c == '"' {
+                        quoted = !quoted;
+                    }
+                    word.push(c);
+                    let _ = chars.next();
+                    if c == '\\' {
+                        if let Some(c) = chars.next() {
+                            word.push(c);
+                        }
+                    }
+                }
+                Token::Operand(match word.as_str() {
+                    "null" => Operand::Literal(Literal::Null),
+                    "true" => Operand::Literal(Literal::Boolean(true)),
+                    "false" => Operand::Literal(Literal::Boolean(false)),
+                    _ => Operand::Path(parse_path(&word)?),
+                })
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn parse_path(path_str: &str) -> Result<KeyPath> {
+    let key_path = KeyPath::from_kp_str(path_str.strip_prefix('.').unwrap_or(path_str))?;
+    if key_path.has_wildcards() || key_path.slice().is_some() {
+        return Err(eyre!(
+            "Wildcards & slices are not allowed in predicate path \"{path_str}\""
+        ));
+    }
+    Ok(key_path)
+}
+
+/// A recursive descent parser over the tokens of a predicate.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Predicate> {
+        let mut predicate = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            predicate = Predicate::Or(Box::new(predicate), Box::new(self.and()?));
+        }
+        Ok(predicate)
+    }
+
+    fn and(&mut self) -> Result<Predicate> {
+        let mut predicate = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            predicate = Predicate::And(Box::new(predicate), Box::new(self.unary()?));
+        }
+        Ok(predicate)
+    }
+
+    fn unary(&mut self) -> Result<Predicate> {
+        match self.next() {
+            Some(Token::Not) => Ok(Predicate::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let predicate = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(predicate),
+                    _ => Err(eyre!("Expecting ')' in predicate")),
+                }
+            }
+            Some(Token::Operand(left)) => match self.peek() {
+                Some(&Token::Compare(comparison)) => {
+                    self.position += 1;
+                    match self.next() {
+                        Some(Token::Operand(right)) => {
+                            Ok(Predicate::Compare(left, comparison, right))
+                        }
+                        _ => Err(eyre!("Expecting a value after {comparison:?} in predicate")),
+                    }
+                }
+                _ => Ok(Predicate::Truthy(left)),
+            },
+            Some(token) => Err(eyre!("Unexpected {token:?} in predicate")),
+            None => Err(eyre!("Unexpected end of predicate")),
+        }
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = Report;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let predicate = parser.or()?;
+        match parser.peek() {
+            None => Ok(predicate),
+            Some(token) => Err(eyre!("Unexpected {token:?} in predicate")),
+        }
+    }
+}
+
+/// A value found in an element. Arrays & objects can only be tested for truth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value<'a> {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(&'a str),
+    Container,
+}
+
+/// The index of the event after the value that starts at `position`.
+fn skip_value(events: &[OwnedJsonEvent], mut position: usize) -> usize {
+    let mut depth = 0;
+    while let Some(event) = events.get(position) {
+        position += 1;
+        match event {
+            OwnedJsonEvent::StartArray | OwnedJsonEvent::StartObject => depth += 1,
+            OwnedJsonEvent::EndArray | OwnedJsonEvent::EndObject => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+    position
+}
+
+/// Find the value at the end of `path` in the events of an element.
+fn lookup<'a>(events: &'a [OwnedJsonEvent], path: &KeyPath) -> Value<'a> {
+    let mut position = 0;
+    for segment in path.iterator() {
+        match (events.get(position), segment) {
+            (Some(OwnedJsonEvent::StartObject), KeySegment::ObjectKey(key)) => {
+                position += 1;
+                loop {
+                    match events.get(position) {
+                        Some(OwnedJsonEvent::ObjectKey(k)) if k == key => {
+                            position += 1;
+                            break;
+                        }
+                        Some(OwnedJsonEvent::ObjectKey(_)) => {
+                            position = skip_value(events, position + 1);
+                        }
+                        _ => return Value::Null,
+                    }
+                }
+            }
+            (Some(OwnedJsonEvent::StartArray), KeySegment::ArrayIndex(index)) => {
+                position += 1;
+                for _ in 0..*index {
+                    if events.get(position) == Some(&OwnedJsonEvent::EndArray) {
+                        return Value::Null;
+                    }
+                    position = skip_value(events, position);
+                }
+            }
+            _ => return Value::Null,
+        }
+    }
+    match events.get(position) {
+        Some(OwnedJsonEvent::Boolean(b)) => Value::Boolean(*b),
+        Some(OwnedJsonEvent::Number(n)) => n.parse().map_or(Value::Null, Value::Number),
+        Some(OwnedJsonEvent::String(s)) => Value::String(s),
+        Some(OwnedJsonEvent::StartArray | OwnedJsonEvent::StartObject) => Value::Container,
+        _ => Value::Null,
+    }
+}
+
+impl Operand {
+    fn value<'a>(&'a self, events: &'a [OwnedJsonEvent]) -> Value<'a> {
+        match self {
+            Self::Path(path) => lookup(events, path),
+            Self::Literal(Literal::Null) => Value::Null,
+            Self::Literal(Literal::Boolean(b)) => Value::Boolean(*b),
+            Self::Literal(Literal::Number(n)) => Value::Number(*n),
+            Self::Literal(Literal::String(s)) => Value::String(s),
+        }
+    }
+}
+
+impl Predicate {
+    /// Whether the element made up of `events` meets the condition.
+    pub fn matches(&self, events: &[OwnedJsonEvent]) -> bool {
+        match self {
+            Self::Compare(left, comparison, right) => {
+                let (left, right) = (left.value(events), right.value(events));
+                let ordering = match (left, right) {
+                    (Value::Container, _) | (_, Value::Container) => None,
+                    (Value::Number(l), Value::Number(r)) => l.partial_cmp(&r),
+                    (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+                    _ if left == right => Some(Ordering::Equal),
+                    _ => None,
+                };
+                match comparison {
+                    Comparison::Eq => ordering == Some(Ordering::Equal),
+                    Comparison::Ne => ordering != Some(Ordering::Equal),
+                    Comparison::Lt => ordering.is_some_and(Ordering::is_lt),
+                    Comparison::Le => ordering.is_some_and(Ordering::is_le),
+                    Comparison::Gt => ordering.is_some_and(Ordering::is_gt),
+                    Comparison::Ge => ordering.is_some_and(Ordering::is_ge),
+                }
+            }
+            Self::Truthy(operand) => {
+                !matches!(operand.value(events), Value::Null | Value::Boolean(false))
+            }
+            Self::Not(predicate) => !predicate.matches(events),
+            Self::And(left, right) => left.matches(events) && right.matches(events),
+            Self::Or(left, right) => left.matches(events) || right.matches(events),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key_path::{KeyPath, OwnedJsonEvent};
+    use crate::predicate::{Comparison, Literal, Operand, Predicate};
+    use json_event_parser::{JsonEvent, JsonReader};
+    use std::str::FromStr;
+
+    fn events(json: &str) -> Vec<OwnedJsonEvent> {
+        let mut reader = JsonReader::from_reader(json.as_bytes());
+        let mut buffer = Vec::new();
+        let mut events = Vec::new();
+        loop {
+            match reader.read_event(&mut buffer).unwrap() {
+                JsonEvent::Eof => return events,
+                event => events.push(event.into()),
+            }
+        }
+    }
+
+    fn matches(predicate: &str, json: &str) -> bool {
+        Predicate::from_str(predicate)
+            .unwrap()
+            .matches(&events(json))
+    }
+
+    #[test]
+    fn test_parse() {
+        let predicate = Predicate::from_str("status == \"failed\"").unwrap();
+        let expected = Predicate::Compare(
+            Operand::Path(KeyPath::from_kp_str("status").unwrap()),
+            Comparison::Eq,
+            Operand::Literal(Literal::String("failed".to_string())),
+        );
+        assert_eq!(expected, predicate);
+        let predicate = Predicate::from_str("!.a.b[2] || .c>=-1.5e1&&(x)").unwrap();
+        let expected = Predicate::Or(
+            Box::new(Predicate::Not(Box::new(Predicate::Truthy(Operand::Path(
+                KeyPath::from_kp_str("a.b[2]").unwrap(),
+            ))))),
+            Box::new(Predicate::And(
+                Box::new(Predicate::Compare(
+                    Operand::Path(KeyPath::from_kp_str("c").unwrap()),
+                    Comparison::Ge,
+                    Operand::Literal(Literal::Number(-15.0)),
+                )),
+                Box::new(Predicate::Truthy(Operand::Path(
+                    KeyPath::from_kp_str("x").unwrap(),
+                ))),
+            )),
+        );
+        assert_eq!(expected, predicate);
+    }
+
+    #[test]
+    fn test_bad_predicates() {
+        for bad in [
+            "", "a ==", "a = 1", "(a", "a b", "\"x", "a.*.b", "a[1:]", "a & b", "== 1",
+        ] {
+            assert!(Predicate::from_str(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let record =
+            "{\"status\":\"failed\",\"amount\":1500,\"tags\":[\"x\",\"y\"],\"a b\":{\"c\":null}}";
+        assert!(matches("status == \"failed\"", record));
+        assert!(!matches("status != \"failed\"", record));
+        assert!(matches(".amount > 1000", record));
+        assert!(matches(".amount >= 1500 && .amount <= 1500", record));
+        assert!(!matches(".amount < 1000", record));
+        assert!(matches("tags[1] == \"y\"", record));
+        assert!(matches("status > \"a\"", record));
+        assert!(!matches("status > 1", record));
+        assert!(matches("missing == null", record));
+        assert!(matches("[\"a b\"].c == null", record));
+        assert!(!matches("tags == \"x\"", record));
+        assert!(matches("tags != \"x\"", record));
+        assert!(matches(". == 1", "1"));
+        assert!(matches(". == \"x\\\"y\"", "\"x\\\"y\""));
+    }
+
+    #[test]
+    fn test_truthiness() {
+        let record = "{\"deleted\":false,\"n\":0,\"o\":{},\"z\":null}";
+        assert!(!matches("deleted", record));
+        assert!(matches("!deleted", record));
+        assert!(matches("n && o", record));
+        assert!(!matches("z || missing", record));
+        assert!(matches("(z || n) && !(deleted)", record));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::key_path::{KeyPath, KeySegment, OwnedJsonEvent};
+use eyre::{eyre, Result};
+use json_event_parser::JsonEvent;
+
+/// How far along a field path a member is: the index of the path, & the number of its segments
+/// that lead to the member.
+type FieldState = (usize, usize);
+
+/// What happens to a member of an element, given the field paths that lead to it.
+#[derive(Clone, Debug, PartialEq)]
+enum Member {
+    /// Kept, along with everything inside it.
+    Whole,
+    /// Kept if it is an array or object, but only with the members that the field paths lead to.
+    Partial(Vec<FieldState>),
+    Dropped,
+}
+
+/// An array or object that is open inside the element being projected.
+struct Frame {
+    /// For an array, the index of the next element.
+    next_index: Option<usize>,
+    /// What is kept of the container's members.
+    contents: Member,
+    /// For an object, what happens to the member whose key was read last.
+    member: Member,
+}
+
+/// What to do with an event inside an element of a target array.
+#[derive(Debug, PartialEq)]
+pub enum Projected {
+    Keep,
+    /// Keep the event, after writing out the key of the member that it is the value of.
+    KeepAfterKey(String),
+    Drop,
+}
+
+/// Keeps only the listed fields of the elements of the target arrays, or every field except the
+/// listed ones, as the events of the elements stream past.
+pub struct Projection {
+    fields: Vec<KeyPath>,
+    exclude: bool,
+    stack: Vec<Frame>,
+    /// The number of containers open inside a member that is being dropped.
+    dropping: usize,
+    /// The key of a member that is only kept if its value turns out to be an array or object.
+    held_key: Option<String>,
+}
+
+impl Projection {
+    fn new(fields: Vec<KeyPath>, exclude: bool) -> Result<Self> {
+        for field in &fields {
+            if field.len() == 0 || field.slice().is_some() {
+                return Err(eyre!(
+                    "A field must be a key path without a slice, found \"{}\"",
+                    field.to_pointer()
+                ));
+            }
+        }
+        Ok(Self {
+            fields,
+            exclude,
+            stack: Vec::new(),
+            dropping: 0,
+            held_key: None,
+        })
+    }
+
+    /// Keep only the members of each element that `fields` lead to.
+    pub fn include(fields: Vec<KeyPath>) -> Result<Self> {
+        Self::new(fields, false)
+    }
+
+    /// Keep every member of each element except the ones that `fields` lead to.
+    pub fn exclude(fields: Vec<KeyPath>) -> Result<Self> {
+        Self::new(fields, true)
+    }
+
+    /// Add `state` to `states`, along with the states that it also stands for, because a `..`
+    /// segment can match no levels at all.
+    fn add_state(&self, states: &mut Vec<FieldState>, (field, mut matched): FieldState) {
+        loop {
+            if !states.contains(&(field, matched)) {
+                states.push((field, matched));
+            }
+            if self.fields[field].get(matched) != Some(&KeySegment::AnyDescendants) {
+                return;
+            }
+            matched += 1;
+        }
+    }
+
+    /// Decide what happens to a member of a container, from what is kept of the container.
+    fn member(&self, contents: &Member, matches: impl Fn(&KeySegment) -> bool) -> Member {
+        let Member::Partial(states) = contents else {
+            return contents.clone();
+        };
+        let mut next = Vec::new();
+        for &(field, matched) in states {
+            match self.fields[field].get(matched) {
+                Some(KeySegment::AnyDescendants) => self.add_state(&mut next, (field, matched)),
+                Some(segment) if matches(segment) => {
+                    self.add_state(&mut next, (field, matched + 1))
+                }
+                _ => {}
+            }
+        }
+        let complete = next
+            .iter()
+            .any(|(field, matched)| self.fields[*field].len() == *matched);
+        match (complete, next.is_empty(), self.exclude) {
+            (true, _, false) => Member::Whole,
+            (true, _, true) => Member::Dropped,
+            (false, true, false) => Member::Dropped,
+            (false, true, true) => Member::Whole,
+            (false, false, _) => Member::Partial(next),
+        }
+    }
+
+    /// Decide what to do with the next event of an element. Every event from the start of an
+    /// element to its end must be passed in, in order.
+    pub fn project(&mut self, event: JsonEvent) -> Projected {
+        if self.dropping > 0 {
+            match event {
+                JsonEvent::StartArray | JsonEvent::StartObject => self.dropping += 1,
+                JsonEvent::EndArray | JsonEvent::EndObject => self.dropping -= 1,
+                _ => {}
+            }
+            return Projected::Drop;
+        }
+        let is_start = matches!(event, JsonEvent::StartArray | JsonEvent::StartObject);
+        let member = match (event, self.stack.last()) {
+            (JsonEvent::EndArray | JsonEvent::EndObject, _) => {
+                let _ = self.stack.pop();
+                return Projected::Keep;
+            }
+            (JsonEvent::ObjectKey(key), Some(frame)) => {
+                let member = self.member(&frame.contents, |segment| match segment {
+                    KeySegment::ObjectKey(k) => k == key,
+                    segment => segment == &KeySegment::AnyKey,
+                });
+                let projected = match member {
+                    Member::Whole => Projected::Keep,
+                    Member::Partial(_) if self.exclude => Projected::Keep,
+                    Member::Partial(_) => {
+                        self.held_key = Some(key.to_string());
+                        Projected::Drop
+                    }
+                    Member::Dropped => Projected::Drop,
+                };
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.member = member;
+                }
+                return projected;
+            }
+            (_, None) => {
+                // The element itself is always kept
+                let mut states = Vec::new();
+                for field in 0..self.fields.len() {
+                    self.add_state(&mut states, (field, 0));
+                }
+                Member::Partial(states)
+            }
+            (
+                _,
+                Some(Frame {
+                    next_index: Some(index),
+                    contents,
+                    ..
+                }),
+            ) => {
+                let index = *index;
+                self.member(contents, |segment| {
+                    segment == &KeySegment::ArrayIndex(index) || segment == &KeySegment::AnyIndex
+                })
+            }
+            (_, Some(frame)) => frame.member.clone(),
+        };
+        let is_root = self.stack.is_empty();
+        if let Some(Frame {
+            next_index: Some(index),
+            ..
+        }) = self.stack.last_mut()
+        {
+            *index += 1;
+        }
+        let held_key = self.held_key.take();
+        match member {
+            Member::Dropped => {
+                if is_start {
+                    self.dropping = 1;
+                }
+                Projected::Drop
+            }
+            Member::Partial(_) if !is_start && !is_root && !self.exclude => Projected::Drop,
+            contents => {
+                if is_start {
+                    self.stack.push(Frame {
+                        next_index: (event == JsonEvent::StartArray).then_some(0),
+                        contents,
+                        member: Member::Whole,
+                    });
+                }
+                match held_key {
+                    Some(key) => Projected::KeepAfterKey(key),
+                    None => Projected::Keep,
+                }
+            }
+        }
+    }
+
+    /// Project all of the events of a complete element.
+    pub fn project_all(&mut self, events: Vec<OwnedJsonEvent>) -> Vec<OwnedJsonEvent> {
+        let mut projected = Vec::with_capacity(events.len());
+        for event in events {
+            match self.project(event.as_json_event()) {
+                Projected::Keep => projected.push(event),
+                Projected::KeepAfterKey(key) => {
+                    projected.push(OwnedJsonEvent::ObjectKey(key));
+                    projected.push(event);
+                }
+                Projected::Drop => {}
+            }
+        }
+        projected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key_path::{KeyPath, OwnedJsonEvent};
+    use crate::projection::Projection;
+    use json_event_parser::{JsonEvent, JsonReader, JsonWriter};
+
+    fn project(projection: &mut Projection, json: &str) -> String {
+        let mut reader = JsonReader::from_reader(json.as_bytes());
+        let mut buffer = Vec::new();
+        let mut events = Vec::new();
+        loop {
+            match reader.read_event(&mut buffer).unwrap() {
+                JsonEvent::Eof => break,
+                event => events.push(OwnedJsonEvent::from(event)),
+            }
+        }
+        let mut writer = JsonWriter::from_writer(Vec::new());
+        for event in projection.project_all(events) {
+            writer.write_event(event.as_json_event()).unwrap();
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    fn fields(field_strs: &[&str]) -> Vec<KeyPath> {
+        field_strs
+            .iter()
+            .map(|field| KeyPath::from_kp_str(field).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_include() {
+        let mut projection = Projection::include(fields(&["id", "user.name", "tags[1]"])).unwrap();
+        assert_eq!(
+            "{\"id\":1,\"user\":{\"name\":\"x\"},\"tags\":[\"b\"]}",
+            project(
+                &mut projection,
+                "{\"id\":1,\"payload\":{\"id\":2},\"user\":{\"id\":3,\"name\":\"x\"},\"tags\":[\"a\",\"b\",\"c\"]}"
+            )
+        );
+        // A field that goes deeper than the element does leaves nothing behind
+        assert_eq!(
+            "{\"id\":2}",
+            project(&mut projection, "{\"user\":\"y\",\"id\":2,\"tags\":null}")
+        );
+        assert_eq!("3", project(&mut projection, "3"));
+    }
+
+    #[test]
+    fn test_include_wildcards() {
+        let mut projection = Projection::include(fields(&["items[*].id", "..name"])).unwrap();
+        assert_eq!(
+            "{\"items\":[{\"id\":1},{\"id\":2,\"name\":\"n\"}],\"a\":{\"b\":{\"name\":\"m\"}}}",
+            project(
+                &mut projection,
+                "{\"items\":[{\"id\":1,\"x\":1},{\"id\":2,\"name\":\"n\"}],\"a\":{\"b\":{\"name\":\"m\",\"c\":1}},\"d\":1}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_exclude() {
+        let mut projection =
+            Projection::exclude(fields(&["payload", "user.secret", "..blob"])).unwrap();
+        assert_eq!(
+            "{\"id\":1,\"user\":{\"name\":\"x\"},\"list\":[{\"a\":1}]}",
+            project(
+                &mut projection,
+                "{\"id\":1,\"payload\":[1,{\"x\":2}],\"user\":{\"name\":\"x\",\"secret\":\"s\"},\"list\":[{\"a\":1,\"blob\":\"b\"}]}"
+            )
+        );
+        assert_eq!("[1,2]", project(&mut projection, "[1,2]"));
+    }
+
+    #[test]
+    fn test_bad_fields() {
+        assert!(Projection::include(fields(&["a", ""])).is_err());
+        assert!(Projection::exclude(fields(&["a[1:]"])).is_err());
+    }
+}